@@ -14,30 +14,37 @@
 //! causes `futures` to try writing or flushing again.
 
 use std::cmp;
-use std::io::{self, Read};
+use std::io::{self, Cursor, Read};
 use std::iter::Fuse;
 
 use futures::task;
 use tokio_io::AsyncRead;
 
-use PartialOp;
+use {Metrics, OpKind, PartialOp, aligned_len, trace_op};
 
 /// A wrapper that breaks inner `AsyncRead` instances up according to the
 /// provided iterator.
 ///
+/// To inject faults into the two directions of a duplex stream independently,
+/// split the stream with `AsyncRead::split` first. Then wrap the `ReadHalf` in
+/// a `PartialAsyncRead` and the `WriteHalf` in a `PartialAsyncWrite`, each with
+/// its own operations. `split` puts no `Send` or `'static` bounds on the
+/// stream, and the wrapped halves are `Send` whenever the stream and the
+/// iterators are.
+///
 /// Available with the `tokio` feature.
 ///
 /// # Examples
 ///
 /// ```rust
+/// extern crate futures;
 /// extern crate partial_io;
-/// extern crate tokio_core;
 /// extern crate tokio_io;
 ///
 /// use std::io::{self, Cursor};
 ///
 /// fn main() {
-///     use tokio_core::reactor::Core;
+///     use futures::Future;
 ///     use tokio_io::io::read as tokio_read;
 ///
 ///     use partial_io::{PartialAsyncRead, PartialOp};
@@ -47,13 +54,12 @@ use PartialOp;
 ///     let partial_reader = PartialAsyncRead::new(reader, iter);
 ///     let out = vec![0; 256];
 ///
-///     let mut core = Core::new().unwrap();
-///
 ///     // This future will skip over the WouldBlock and return however much was
-///     // successfully read the first time a read succeeded.
+///     // successfully read the first time a read succeeded. No event loop is
+///     // needed: `wait` runs the future on the current thread.
 ///     let read_fut = tokio_read(partial_reader, out);
 ///
-///     let (_partial_reader, out, size) = core.run(read_fut).unwrap();
+///     let (_partial_reader, out, size) = read_fut.wait().unwrap();
 ///
 ///     assert_eq!(size, 2);
 ///     assert_eq!(&out[..3], &[1, 2, 0]);
@@ -64,6 +70,10 @@ pub struct PartialAsyncRead<R, I>
 {
     inner: R,
     iter: Fuse<I::IntoIter>,
+    metrics: Metrics,
+    max_ops: Option<usize>,
+    // The bytes returned by the last read, for PartialOp::Duplicate.
+    last_read: Vec<u8>,
 }
 
 impl<R, I> PartialAsyncRead<R, I>
@@ -72,11 +82,64 @@ impl<R, I> PartialAsyncRead<R, I>
 {
     pub fn new(inner: R, iter: I) -> Self {
         PartialAsyncRead {
-            inner: inner,
+            inner,
             iter: iter.into_iter().fuse(),
+            metrics: Metrics::default(),
+            max_ops: None,
+            last_read: Vec::new(),
         }
     }
 
+    /// Makes every call fail once more than `max_ops` calls have been made.
+    ///
+    /// This is a guardrail against runaway tests: if the code under test gets
+    /// stuck in a loop, it fails fast with an `Other` error saying that the
+    /// partial-io operation limit was exceeded, instead of spinning forever.
+    /// Every `read` call counts towards the limit, whatever operation it ends
+    /// up with, so each time a future polls this reader counts. Once the limit
+    /// is exceeded, calls no longer reach the underlying reader or take
+    /// operations from the iterator.
+    ///
+    /// There is no limit by default.
+    pub fn set_max_ops(&mut self, max_ops: usize) {
+        self.max_ops = Some(max_ops);
+    }
+
+    /// Returns a snapshot of the counters kept by this wrapper.
+    ///
+    /// See [`Metrics`](struct.Metrics.html) for more.
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.clone()
+    }
+
+    /// Returns the number of calls that reached the underlying reader.
+    ///
+    /// See [`Metrics::inner_io_calls`](struct.Metrics.html#structfield.inner_io_calls)
+    /// for more.
+    pub fn inner_io_calls(&self) -> usize {
+        self.metrics.inner_io_calls
+    }
+
+    /// Returns the number of times this reader returned `Ok(0)` for a
+    /// non-empty buffer, signaling the end of the stream.
+    ///
+    /// Code that reads until the end of the stream should see this exactly once.
+    /// More than that means it kept reading after the end, and 0 means it never
+    /// got there.
+    pub fn eof_seen_count(&self) -> usize {
+        self.metrics.eofs
+    }
+
+    /// Returns whether the most recent call applied an operation from the
+    /// iterator, or passed straight through to the underlying reader.
+    ///
+    /// Returns `None` if no calls have been made yet. See
+    /// [`Metrics::last_op_injected`](struct.Metrics.html#structfield.last_op_injected)
+    /// for more.
+    pub fn last_op_was_injected(&self) -> Option<bool> {
+        self.metrics.last_op_injected
+    }
+
     /// Acquires a reference to the underlying reader.
     pub fn get_ref(&self) -> &R {
         &self.inner
@@ -93,26 +156,165 @@ impl<R, I> PartialAsyncRead<R, I>
     }
 }
 
+/// One scripted result for [`PartialAsyncRead::from_polls`].
+///
+/// [`PartialAsyncRead::from_polls`]: struct.PartialAsyncRead.html#method.from_polls
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PollResult {
+    /// The poll returns `Async::NotReady`, and the task is woken up again.
+    Pending,
+    /// The poll is ready with these bytes. An empty `Vec` signals the end of
+    /// the stream.
+    Ready(Vec<u8>),
+    /// The poll fails with an error of this kind.
+    Err(io::ErrorKind),
+}
+
+impl PartialAsyncRead<Cursor<Vec<u8>>, Vec<PartialOp>> {
+    /// Creates a new reader that returns exactly the given results, one per
+    /// poll, in order.
+    ///
+    /// This gives byte-exact and poll-exact control over what the code under
+    /// test sees, which is useful for testing state machines. Once the results
+    /// run out, every poll signals the end of the stream.
+    ///
+    /// The bytes for a `Ready` result are only delivered in a single poll if
+    /// the buffer passed in is large enough. If it isn't, the buffer is filled
+    /// and the rest of the bytes are delivered ahead of those for the next
+    /// `Ready` result.
+    ///
+    /// The returned reader is an ordinary `PartialAsyncRead` over all the bytes
+    /// concatenated together, so `into_inner` and `get_ref` don't return
+    /// anything meaningful.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// extern crate futures;
+    /// extern crate partial_io;
+    /// extern crate tokio_io;
+    ///
+    /// use std::io;
+    ///
+    /// fn main() {
+    ///     use futures::Future;
+    ///     use futures::future::poll_fn;
+    ///     use tokio_io::AsyncRead;
+    ///
+    ///     use partial_io::{PartialAsyncRead, PollResult};
+    ///
+    ///     let polls = vec![PollResult::Pending,
+    ///                      PollResult::Ready(b"abc".to_vec()),
+    ///                      PollResult::Err(io::ErrorKind::Interrupted),
+    ///                      PollResult::Ready(b"de".to_vec())];
+    ///     let mut partial_reader = PartialAsyncRead::from_polls(polls);
+    ///     let mut buf = [0; 16];
+    ///
+    ///     // The Pending is skipped over by the wakeup.
+    ///     let n = poll_fn(|| partial_reader.poll_read(&mut buf)).wait().unwrap();
+    ///     assert_eq!(&buf[..n], b"abc");
+    ///     let err = poll_fn(|| partial_reader.poll_read(&mut buf)).wait().unwrap_err();
+    ///     assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+    ///     let n = poll_fn(|| partial_reader.poll_read(&mut buf)).wait().unwrap();
+    ///     assert_eq!(&buf[..n], b"de");
+    ///     let n = poll_fn(|| partial_reader.poll_read(&mut buf)).wait().unwrap();
+    ///     assert_eq!(n, 0);
+    /// }
+    /// ```
+    pub fn from_polls(polls: Vec<PollResult>) -> Self {
+        let mut data = Vec::new();
+        let ops = polls
+            .into_iter()
+            .map(|poll| match poll {
+                     PollResult::Pending => PartialOp::Err(io::ErrorKind::WouldBlock),
+                     PollResult::Ready(bytes) => {
+                         data.extend_from_slice(&bytes);
+                         PartialOp::Limited(bytes.len())
+                     }
+                     PollResult::Err(err) => PartialOp::Err(err),
+                 })
+            .collect();
+        PartialAsyncRead::new(Cursor::new(data), ops)
+    }
+}
+
 impl<R, I> Read for PartialAsyncRead<R, I>
     where R: AsyncRead,
           I: IntoIterator<Item = PartialOp>
 {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        match self.iter.next() {
+        self.metrics.call(buf.len());
+        if let Some(err) = self.metrics.limit_exceeded(self.max_ops, OpKind::Read) {
+            let res = Err(err);
+            trace_op(OpKind::Read, &self.metrics, &None, Some(buf.len()), &res);
+            return res;
+        }
+        let op = self.metrics.op(self.iter.next().map(PartialOp::for_read));
+        let duplicate = match op {
+            Some(PartialOp::Duplicate) => !self.last_read.is_empty(),
+            _ => false,
+        };
+        let res = match op {
             Some(PartialOp::Limited(n)) => {
                 let len = cmp::min(n, buf.len());
-                self.inner.read(&mut buf[..len])
+                self.metrics.transferred(self.inner.read(&mut buf[..len]))
+            }
+            Some(PartialOp::AlignedTo(align)) => {
+                let len = aligned_len(buf.len(), align);
+                if len == 0 && !buf.is_empty() {
+                    Err(self.metrics.injected(io::ErrorKind::InvalidInput,
+                                              OpKind::Read,
+                                              "buffer smaller than alignment during read, \
+                                               generated by partial-io"))
+                } else {
+                    self.metrics.transferred(self.inner.read(&mut buf[..len]))
+                }
             }
+            // The count is replaced below, once the bytes have been remembered.
+            Some(PartialOp::ReportExactly(_)) => self.metrics.transferred(self.inner.read(buf)),
             Some(PartialOp::Err(err)) => {
                 if err == io::ErrorKind::WouldBlock {
                     // Make sure this task is rechecked.
-                    task::park().unpark();
+                    task::current().notify();
                 }
-                Err(io::Error::new(err, "error during read, generated by partial-io"))
+                Err(self.metrics.injected(err,
+                                          OpKind::Read,
+                                          "error during read, generated by partial-io"))
             }
+            Some(PartialOp::Duplicate) if duplicate => {
+                let len = cmp::min(self.last_read.len(), buf.len());
+                buf[..len].copy_from_slice(&self.last_read[..len]);
+                self.metrics.buffered(len)
+            }
+            // With nothing to duplicate, this is a passthrough.
+            Some(PartialOp::Duplicate) |
+            // Only PartialRead supports this.
+            Some(PartialOp::ErrOnLast(_)) |
+            // Direction-specific operations have already been resolved.
+            Some(PartialOp::DropAfter(_)) |
+            Some(PartialOp::ErrAfterFlush(_)) |
+            Some(PartialOp::ReadLimited(_)) |
+            Some(PartialOp::WriteLimited(_)) |
+            Some(PartialOp::ReadErr(_)) |
+            Some(PartialOp::WriteErr(_)) |
+            Some(PartialOp::AsyncWriteZero) |
+            Some(PartialOp::VectoredLimited(_)) |
             Some(PartialOp::Unlimited) |
-            None => self.inner.read(buf),
+            None => self.metrics.transferred(self.inner.read(buf)),
+        };
+        if !duplicate {
+            self.last_read.clear();
+            if let Ok(n) = res {
+                self.last_read.extend_from_slice(&buf[..cmp::min(n, buf.len())]);
+            }
         }
+        let res = match op {
+            Some(PartialOp::ReportExactly(n)) => res.map(|_| n),
+            _ => res,
+        };
+        self.metrics.read_returned(buf.len(), &res);
+        trace_op(OpKind::Read, &self.metrics, &op, Some(buf.len()), &res);
+        res
     }
 }
 
@@ -14,24 +14,26 @@
 //! causes `futures` to try writing or flushing again.
 
 use std::cmp;
-use std::io::{self, Write};
+use std::io::{self, Cursor, IoSlice, Write};
 use std::iter::Fuse;
 
-use futures::{Poll, task};
+use futures::{Async, Future, Poll, task};
 use tokio_io::AsyncWrite;
 
-use PartialOp;
+use {Metrics, OpKind, PartialIoError, PartialOp, aligned_len, capped_slices, trace_op};
 
 /// A wrapper that breaks inner `AsyncWrite` instances up according to the
 /// provided iterator.
 ///
+/// See [`PartialAsyncRead`] for how to wrap the halves of a split stream.
+///
 /// Available with the `tokio` feature.
 ///
 /// # Examples
 ///
 /// ```rust
+/// extern crate futures;
 /// extern crate partial_io;
-/// extern crate tokio_core;
 /// extern crate tokio_io;
 ///
 /// use std::io::{self, Cursor};
@@ -39,7 +41,7 @@ use PartialOp;
 /// fn main() {
 ///     // Note that this test doesn't demonstrate a limited write because
 ///     // tokio-io doesn't have a combinator for that, just write_all.
-///     use tokio_core::reactor::Core;
+///     use futures::Future;
 ///     use tokio_io::io::write_all;
 ///
 ///     use partial_io::{PartialAsyncWrite, PartialOp};
@@ -49,21 +51,28 @@ use PartialOp;
 ///     let partial_writer = PartialAsyncWrite::new(writer, iter);
 ///     let in_data = vec![1, 2, 3, 4];
 ///
-///     let mut core = Core::new().unwrap();
-///
+///     // No event loop is needed: `wait` runs the future on the current
+///     // thread.
 ///     let write_fut = write_all(partial_writer, in_data);
 ///
-///     let (partial_writer, _in_data) = core.run(write_fut).unwrap();
+///     let (partial_writer, _in_data) = write_fut.wait().unwrap();
 ///     let cursor = partial_writer.into_inner();
 ///     let out = cursor.into_inner();
 ///     assert_eq!(&out, &[1, 2, 3, 4]);
 /// }
 /// ```
+///
+/// [`PartialAsyncRead`]: struct.PartialAsyncRead.html
 pub struct PartialAsyncWrite<W, I>
     where I: IntoIterator<Item = PartialOp>
 {
     inner: W,
     iter: Fuse<I::IntoIter>,
+    metrics: Metrics,
+    max_ops: Option<usize>,
+    write_sizes: Option<Vec<usize>>,
+    // Set once a DropAfter operation has been taken.
+    drop_after: Option<usize>,
 }
 
 impl<W, I> PartialAsyncWrite<W, I>
@@ -72,13 +81,86 @@ impl<W, I> PartialAsyncWrite<W, I>
 {
     pub fn new(inner: W, iter: I) -> Self {
         PartialAsyncWrite {
-            inner: inner,
+            inner,
             // Use fuse here so that we don't keep calling the inner iterator
             // once it's returned None.
             iter: iter.into_iter().fuse(),
+            metrics: Metrics::default(),
+            max_ops: None,
+            write_sizes: None,
+            drop_after: None,
+        }
+    }
+
+    /// Makes every call fail once more than `max_ops` calls have been made.
+    ///
+    /// This is a guardrail against runaway tests: if the code under test gets
+    /// stuck in a loop, it fails fast with an `Other` error saying that the
+    /// partial-io operation limit was exceeded, instead of spinning forever.
+    /// Every `write`, `write_vectored` and `flush` call counts towards the
+    /// limit, whatever operation it ends up with, so each time a future polls
+    /// this writer counts. Once the limit is exceeded, calls no longer reach
+    /// the underlying writer or take operations from the iterator.
+    ///
+    /// There is no limit by default.
+    pub fn set_max_ops(&mut self, max_ops: usize) {
+        self.max_ops = Some(max_ops);
+    }
+
+    /// Returns a snapshot of the counters kept by this wrapper.
+    ///
+    /// See [`Metrics`](struct.Metrics.html) for more.
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.clone()
+    }
+
+    /// Returns the number of calls that reached the underlying writer.
+    ///
+    /// See [`Metrics::inner_io_calls`](struct.Metrics.html#structfield.inner_io_calls)
+    /// for more.
+    pub fn inner_io_calls(&self) -> usize {
+        self.metrics.inner_io_calls
+    }
+
+    /// Starts recording the size of the buffer passed into each `write` call.
+    ///
+    /// The sizes are returned by [`inner_write_sizes`]. Nothing is recorded
+    /// unless this is called, so that long streams don't build up an
+    /// ever-growing list.
+    ///
+    /// [`inner_write_sizes`]: #method.inner_write_sizes
+    pub fn record_write_sizes(&mut self) {
+        if self.write_sizes.is_none() {
+            self.write_sizes = Some(Vec::new());
         }
     }
 
+    /// Returns the size of the buffer passed into each `write` call since
+    /// [`record_write_sizes`] was called, in order.
+    ///
+    /// This records what the code under test asked to write, before any
+    /// operations are applied. It is useful for checking how calls are
+    /// batched: for example, that a buffered writer wrote `[4096, 4096, 128]`.
+    /// Returns an empty slice if sizes aren't being recorded.
+    ///
+    /// [`record_write_sizes`]: #method.record_write_sizes
+    pub fn inner_write_sizes(&self) -> &[usize] {
+        match self.write_sizes {
+            Some(ref sizes) => sizes,
+            None => &[],
+        }
+    }
+
+    /// Returns whether the most recent call applied an operation from the
+    /// iterator, or passed straight through to the underlying writer.
+    ///
+    /// Returns `None` if no calls have been made yet. See
+    /// [`Metrics::last_op_injected`](struct.Metrics.html#structfield.last_op_injected)
+    /// for more.
+    pub fn last_op_was_injected(&self) -> Option<bool> {
+        self.metrics.last_op_injected
+    }
+
     /// Acquires a reference to the underlying writer.
     pub fn get_ref(&self) -> &W {
         &self.inner
@@ -93,37 +175,280 @@ impl<W, I> PartialAsyncWrite<W, I>
     pub fn into_inner(self) -> W {
         self.inner
     }
+
+    /// Tears this wrapper down, returning a future that resolves to the
+    /// underlying writer.
+    ///
+    /// The future flushes the wrapper, then shuts it down, retrying both on
+    /// `WouldBlock` until they complete. Operations are taken from the
+    /// iterator as usual along the way. Once the shutdown completes, the
+    /// future checks that the iterator has no operations left, and fails with
+    /// an `Other` error if it does, since that usually means the code under
+    /// test made fewer calls than the test expected. Any error from the flush
+    /// or the shutdown is returned as is.
+    ///
+    /// Note that the check takes an operation from the iterator to see if any
+    /// are left. If there is one, it is silently consumed: it isn't applied,
+    /// counted in the metrics or named in the error. The check always fails
+    /// with endless iterators, so it should not be used with them.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// extern crate futures;
+    /// extern crate partial_io;
+    /// extern crate tokio_io;
+    ///
+    /// use std::io;
+    ///
+    /// fn main() {
+    ///     use futures::Future;
+    ///     use tokio_io::io::write_all;
+    ///
+    ///     use partial_io::{PartialAsyncWrite, PartialOp};
+    ///
+    ///     let ops = vec![PartialOp::Limited(5), PartialOp::Err(io::ErrorKind::WouldBlock)];
+    ///     let partial_writer = PartialAsyncWrite::collecting(ops);
+    ///     let (partial_writer, _) = write_all(partial_writer, b"Hello").wait().unwrap();
+    ///
+    ///     // The flush skips over the WouldBlock, and then every operation has
+    ///     // been used.
+    ///     let cursor = partial_writer.finish().wait().unwrap();
+    ///     assert_eq!(cursor.get_ref(), b"Hello");
+    /// }
+    /// ```
+    pub fn finish(self) -> Finish<W, I> {
+        Finish {
+            writer: Some(self),
+            flushed: false,
+        }
+    }
 }
 
-impl<W, I> Write for PartialAsyncWrite<W, I>
+impl<W, I> PartialAsyncWrite<W, I>
     where W: Write,
           I: IntoIterator<Item = PartialOp>
 {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        match self.iter.next() {
+    /// Applies `op` to a `write` call for `buf`.
+    fn write_op(&mut self, op: &Option<PartialOp>, buf: &[u8]) -> io::Result<usize> {
+        match *op {
             Some(PartialOp::Limited(n)) => {
                 let len = cmp::min(n, buf.len());
-                self.inner.write(&buf[..len])
+                self.metrics.transferred(self.inner.write(&buf[..len]))
+            }
+            Some(PartialOp::AlignedTo(align)) => {
+                let len = aligned_len(buf.len(), align);
+                if len == 0 && !buf.is_empty() {
+                    Err(self.metrics.injected(io::ErrorKind::InvalidInput,
+                                              OpKind::Write,
+                                              "buffer smaller than alignment during write, \
+                                               generated by partial-io"))
+                } else {
+                    self.metrics.transferred(self.inner.write(&buf[..len]))
+                }
+            }
+            Some(PartialOp::ReportExactly(n)) => {
+                self.metrics.transferred(self.inner.write(buf)).map(|_| n)
             }
             Some(PartialOp::Err(err)) => {
                 if err == io::ErrorKind::WouldBlock {
                     // Make sure this task is rechecked.
-                    task::park().unpark();
+                    task::current().notify();
                 }
-                Err(io::Error::new(err, "error during write, generated by partial-io"))
+                Err(self.metrics.injected(err,
+                                          OpKind::Write,
+                                          "error during write, generated by partial-io"))
             }
+            Some(PartialOp::VectoredLimited(ref caps)) => {
+                let len = cmp::min(caps.first().cloned().unwrap_or(0), buf.len());
+                self.metrics.transferred(self.inner.write(&buf[..len]))
+            }
+            Some(PartialOp::AsyncWriteZero) => Ok(0),
+            Some(PartialOp::DropAfter(limit)) => self.write_until_drop(buf, limit),
+            // Only PartialWrite supports this.
+            Some(PartialOp::ErrAfterFlush(_)) |
+            // Direction-specific operations have already been resolved.
+            Some(PartialOp::Duplicate) |
+            Some(PartialOp::ErrOnLast(_)) |
+            Some(PartialOp::ReadLimited(_)) |
+            Some(PartialOp::WriteLimited(_)) |
+            Some(PartialOp::ReadErr(_)) |
+            Some(PartialOp::WriteErr(_)) |
             Some(PartialOp::Unlimited) |
-            None => self.inner.write(buf),
+            None => self.metrics.transferred(self.inner.write(buf)),
+        }
+    }
+
+    /// Appends `len` to the recorded write sizes, if recording.
+    fn record_write_size(&mut self, len: usize) {
+        if let Some(sizes) = self.write_sizes.as_mut() {
+            sizes.push(len);
+        }
+    }
+
+    /// Writes through to the inner writer, up to a total of `limit` bytes.
+    fn write_until_drop(&mut self, buf: &[u8], limit: usize) -> io::Result<usize> {
+        self.drop_after = Some(limit);
+        if self.is_dropped() {
+            return Err(self.dropped_err(OpKind::Write));
+        }
+        let len = cmp::min(limit - self.metrics.bytes, buf.len());
+        self.metrics.transferred(self.inner.write(&buf[..len]))
+    }
+
+    /// Flushes the inner writer, unless `limit` bytes have been written.
+    fn flush_until_drop(&mut self, limit: usize) -> io::Result<()> {
+        self.drop_after = Some(limit);
+        if self.is_dropped() {
+            return Err(self.dropped_err(OpKind::Flush));
+        }
+        self.metrics.inner(self.inner.flush())
+    }
+
+    /// Returns whether a `DropAfter` operation has cut the connection.
+    fn is_dropped(&self) -> bool {
+        match self.drop_after {
+            Some(limit) => self.metrics.bytes >= limit,
+            None => false,
+        }
+    }
+
+    fn dropped_err(&mut self, op_kind: OpKind) -> io::Error {
+        let message = match op_kind {
+            OpKind::Flush => "connection dropped during flush, generated by partial-io",
+            _ => "connection dropped during write, generated by partial-io",
+        };
+        self.metrics.injected(io::ErrorKind::ConnectionReset, op_kind, message)
+    }
+}
+
+impl<I> PartialAsyncWrite<Cursor<Vec<u8>>, I>
+    where I: IntoIterator<Item = PartialOp>
+{
+    /// Creates a new wrapper around an empty in-memory buffer, which collects
+    /// every byte that makes it through.
+    ///
+    /// This is a convenience for tests that check exactly what was written.
+    /// Use `collected` to look at the bytes, and `into_collected` to take them
+    /// out once the wrapper is no longer needed.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// extern crate futures;
+    /// extern crate partial_io;
+    /// extern crate tokio_io;
+    ///
+    /// use std::io;
+    ///
+    /// fn main() {
+    ///     use futures::Future;
+    ///     use tokio_io::io::write_all;
+    ///
+    ///     use partial_io::{PartialAsyncWrite, PartialOp};
+    ///
+    ///     let ops = vec![PartialOp::Err(io::ErrorKind::WouldBlock), PartialOp::Limited(2)];
+    ///     let partial_writer = PartialAsyncWrite::collecting(ops);
+    ///     let (partial_writer, _) = write_all(partial_writer, b"Hello").wait().unwrap();
+    ///     assert_eq!(partial_writer.collected(), b"Hello");
+    /// }
+    /// ```
+    pub fn collecting(iter: I) -> Self {
+        PartialAsyncWrite::new(Cursor::new(Vec::new()), iter)
+    }
+
+    /// Returns the bytes collected so far.
+    pub fn collected(&self) -> &[u8] {
+        self.inner.get_ref()
+    }
+
+    /// Consumes this wrapper, returning the bytes collected.
+    pub fn into_collected(self) -> Vec<u8> {
+        self.inner.into_inner()
+    }
+}
+
+impl<W, I> Write for PartialAsyncWrite<W, I>
+    where W: Write,
+          I: IntoIterator<Item = PartialOp>
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.metrics.call(buf.len());
+        self.record_write_size(buf.len());
+        if let Some(err) = self.metrics.limit_exceeded(self.max_ops, OpKind::Write) {
+            let res = Err(err);
+            trace_op(OpKind::Write, &self.metrics, &None, Some(buf.len()), &res);
+            return res;
+        }
+        if let Some(limit) = self.drop_after {
+            // Once armed, DropAfter takes over and no more operations are used.
+            let res = self.write_until_drop(buf, limit);
+            trace_op(OpKind::Write, &self.metrics, &None, Some(buf.len()), &res);
+            return res;
         }
+        let op = self.metrics.op(self.iter.next().map(PartialOp::for_write));
+        let res = self.write_op(&op, buf);
+        trace_op(OpKind::Write, &self.metrics, &op, Some(buf.len()), &res);
+        res
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+        let total = bufs.iter().map(|buf| buf.len()).sum();
+        self.metrics.call(total);
+        self.record_write_size(total);
+        if let Some(err) = self.metrics.limit_exceeded(self.max_ops, OpKind::Write) {
+            let res = Err(err);
+            trace_op(OpKind::Write, &self.metrics, &None, Some(total), &res);
+            return res;
+        }
+        // Like the default implementation, only write out the first non-empty
+        // buffer unless the operation says otherwise.
+        let first = bufs.iter().find(|buf| !buf.is_empty()).map_or(&[][..], |buf| &**buf);
+        if let Some(limit) = self.drop_after {
+            let res = self.write_until_drop(first, limit);
+            trace_op(OpKind::Write, &self.metrics, &None, Some(total), &res);
+            return res;
+        }
+        let op = self.metrics.op(self.iter.next().map(PartialOp::for_write));
+        let res = match op {
+            Some(PartialOp::VectoredLimited(ref caps)) => {
+                let capped = capped_slices(bufs, caps);
+                self.metrics.transferred(self.inner.write_vectored(&capped))
+            }
+            _ => self.write_op(&op, first),
+        };
+        trace_op(OpKind::Write, &self.metrics, &op, Some(total), &res);
+        res
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        match self.iter.next() {
+        self.metrics.flush();
+        if let Some(err) = self.metrics.limit_exceeded(self.max_ops, OpKind::Flush) {
+            let res = Err(err);
+            trace_op(OpKind::Flush, &self.metrics, &None, None, &res);
+            return res;
+        }
+        if let Some(limit) = self.drop_after {
+            let res = self.flush_until_drop(limit);
+            trace_op(OpKind::Flush, &self.metrics, &None, None, &res);
+            return res;
+        }
+        let op = self.metrics.op(self.iter.next().map(PartialOp::for_write));
+        let res = match op {
             Some(PartialOp::Err(err)) => {
-                Err(io::Error::new(err, "error during flush, generated by partial-io"))
+                if err == io::ErrorKind::WouldBlock {
+                    // Make sure this task is rechecked.
+                    task::current().notify();
+                }
+                Err(self.metrics.injected(err,
+                                          OpKind::Flush,
+                                          "error during flush, generated by partial-io"))
             }
-            _ => self.inner.flush(),
-        }
+            Some(PartialOp::DropAfter(limit)) => self.flush_until_drop(limit),
+            _ => self.metrics.inner(self.inner.flush()),
+        };
+        trace_op(OpKind::Flush, &self.metrics, &op, None, &res);
+        res
     }
 }
 
@@ -132,6 +457,63 @@ impl<W, I> AsyncWrite for PartialAsyncWrite<W, I>
           I: IntoIterator<Item = PartialOp>
 {
     fn shutdown(&mut self) -> Poll<(), io::Error> {
+        if self.is_dropped() {
+            return Err(self.metrics.injected(io::ErrorKind::ConnectionReset,
+                                             OpKind::Flush,
+                                             "connection dropped during shutdown, \
+                                              generated by partial-io"));
+        }
         self.inner.shutdown()
     }
 }
+
+/// The future returned by [`PartialAsyncWrite::finish`].
+///
+/// Available with the `tokio` feature.
+///
+/// [`PartialAsyncWrite::finish`]: struct.PartialAsyncWrite.html#method.finish
+pub struct Finish<W, I>
+    where I: IntoIterator<Item = PartialOp>
+{
+    writer: Option<PartialAsyncWrite<W, I>>,
+    flushed: bool,
+}
+
+impl<W, I> Future for Finish<W, I>
+    where W: AsyncWrite,
+          I: IntoIterator<Item = PartialOp>
+{
+    type Item = W;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<W, io::Error> {
+        {
+            let writer = self.writer.as_mut().expect("cannot poll Finish twice");
+            if !self.flushed {
+                match writer.flush() {
+                    Ok(()) => self.flushed = true,
+                    Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                        return Ok(Async::NotReady)
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            match writer.shutdown() {
+                Ok(Async::Ready(())) => {}
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(Async::NotReady)
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        let mut writer = self.writer.take().unwrap();
+        if writer.iter.next().is_some() {
+            let err = PartialIoError::not_injected(OpKind::Flush,
+                                                   "partial-io operations left unused at finish");
+            #[allow(clippy::io_other_error)]
+            return Err(io::Error::new(io::ErrorKind::Other, err));
+        }
+        Ok(Async::Ready(writer.inner))
+    }
+}
@@ -0,0 +1,421 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! This module contains an `AsyncWrite` wrapper that buffers writes
+//! internally, and only passes them on to the inner writer when flushed.
+
+use std::cmp;
+use std::io::{self, Write};
+use std::iter::Fuse;
+
+use futures::{Async, Poll, task};
+use tokio_io::AsyncWrite;
+
+use {Metrics, OpKind, PartialIoError, PartialOp, aligned_len, trace_op};
+use monotonic::Monotonic;
+use rng::XorShift;
+
+/// A wrapper that buffers writes to an inner `AsyncWrite` instance, then
+/// breaks the buffered data up according to the provided iterator when
+/// flushed.
+///
+/// This models writers like `std::io::BufWriter` that always accept data on
+/// `write` and only push it out on `flush`. It is useful for testing code that
+/// relies on an explicit flush to get data to its destination.
+///
+/// Every `write` call is accepted in full and appended to an internal buffer,
+/// without consuming any operations. On `flush`, the buffer is drained into the
+/// inner writer, with one operation consumed per inner `write` call. If an
+/// operation returns an error, `flush` fails and whatever was not yet drained
+/// stays buffered for the next `flush`. Once the buffer is empty, the inner
+/// writer is flushed. `shutdown` flushes before shutting down the inner writer.
+///
+/// With the `tracing` feature, every `write` and `flush` call is traced, and so
+/// is each inner write made while flushing.
+///
+/// Available with the `tokio` feature.
+///
+/// # Examples
+///
+/// ```rust
+/// extern crate futures;
+/// extern crate partial_io;
+/// extern crate tokio_io;
+///
+/// use std::io::{self, Cursor, Write};
+///
+/// fn main() {
+///     use futures::Future;
+///     use tokio_io::io::flush;
+///
+///     use partial_io::{CoalescingPartialAsyncWrite, PartialOp};
+///
+///     let writer = Cursor::new(Vec::new());
+///     let iter = vec![PartialOp::Err(io::ErrorKind::WouldBlock), PartialOp::Limited(2)];
+///     let mut partial_writer = CoalescingPartialAsyncWrite::new(writer, iter);
+///
+///     // Writes are always accepted in full, but nothing reaches the inner
+///     // writer yet.
+///     assert_eq!(partial_writer.write(&[1, 2, 3, 4]).unwrap(), 4);
+///     assert!(partial_writer.get_ref().get_ref().is_empty());
+///
+///     // Flushing skips over the WouldBlock and drains the buffer two bytes at
+///     // a time, then without any limits once the operations run out.
+///     let partial_writer = flush(partial_writer).wait().unwrap();
+///     let out = partial_writer.into_inner().into_inner();
+///     assert_eq!(&out, &[1, 2, 3, 4]);
+/// }
+/// ```
+pub struct CoalescingPartialAsyncWrite<W, I>
+    where I: IntoIterator<Item = PartialOp>
+{
+    inner: W,
+    iter: Fuse<I::IntoIter>,
+    metrics: Metrics,
+    write_sizes: Option<Vec<usize>>,
+    buf: Vec<u8>,
+    // The position in buf up to which data has been written to inner.
+    pos: usize,
+    shuffle: Option<XorShift>,
+    // The position in buf up to which data has been shuffled.
+    shuffled: usize,
+    monotonic: Option<Monotonic>,
+}
+
+impl<W, I> CoalescingPartialAsyncWrite<W, I>
+    where W: AsyncWrite,
+          I: IntoIterator<Item = PartialOp>
+{
+    pub fn new(inner: W, iter: I) -> Self {
+        CoalescingPartialAsyncWrite {
+            inner,
+            // Use fuse here so that we don't keep calling the inner iterator
+            // once it's returned None.
+            iter: iter.into_iter().fuse(),
+            metrics: Metrics::default(),
+            write_sizes: None,
+            buf: Vec::new(),
+            pos: 0,
+            shuffle: None,
+            shuffled: 0,
+            monotonic: None,
+        }
+    }
+
+    /// Shuffles buffered bytes before they are written to the inner writer.
+    ///
+    /// This is a severe, intentional data-integrity fault. Every byte still
+    /// reaches the inner writer exactly once, but in a different order than it
+    /// was written in. It is meant for checking that detection logic, like
+    /// checksums or framing, catches reordering done by hostile storage.
+    ///
+    /// The order is chosen by a pseudo-random number generator seeded with
+    /// `seed`, so a failing test can be reproduced by using the same seed with
+    /// the same writes. Bytes are only shuffled among others written since the
+    /// last flush that drained the buffer, and the operations from the
+    /// iterator still apply to the inner writes as usual.
+    ///
+    /// A flush that fails partway leaves the rest of the shuffled bytes in the
+    /// buffer, as returned by [`buffer`]. Retrying the flush writes them out in
+    /// that same order without shuffling them again, so a retry behaves like
+    /// the inner writer resuming a single reordered write. Bytes written after
+    /// the failed flush are shuffled among themselves, and are written after
+    /// the bytes that were already shuffled.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::{Cursor, Write};
+    ///
+    /// use partial_io::CoalescingPartialAsyncWrite;
+    ///
+    /// let mut partial_writer = CoalescingPartialAsyncWrite::new(Cursor::new(Vec::new()), vec![]);
+    /// partial_writer.shuffle_on_flush(42);
+    /// partial_writer.write_all(b"Hello, world!").unwrap();
+    /// partial_writer.flush().unwrap();
+    ///
+    /// let mut out = partial_writer.into_inner().into_inner();
+    /// assert_ne!(&out, b"Hello, world!");
+    /// out.sort();
+    /// let mut expected = b"Hello, world!".to_vec();
+    /// expected.sort();
+    /// assert_eq!(out, expected);
+    /// ```
+    ///
+    /// [`buffer`]: #method.buffer
+    pub fn shuffle_on_flush(&mut self, seed: u64) {
+        self.shuffle = Some(XorShift::new(seed));
+    }
+
+    /// Panics if the bytes forwarded to the underlying writer aren't exactly
+    /// the bytes written to this wrapper, in order.
+    ///
+    /// This is a debugging aid for the wrapper's own buffering logic. From now
+    /// on, the wrapper keeps a copy of every byte that hasn't reached the
+    /// underlying writer yet, and panics during `flush` if an inner write
+    /// forwards data from the wrong offset or forwards bytes that don't match
+    /// the ones written there.
+    ///
+    /// [`shuffle_on_flush`] reorders bytes on purpose, so the check takes the
+    /// shuffled order as the expected one, and only catches bytes that are
+    /// skipped, repeated, or changed after the shuffle. No copying happens
+    /// unless this is called.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::{Cursor, Write};
+    ///
+    /// use partial_io::{CoalescingPartialAsyncWrite, PartialOp};
+    ///
+    /// let ops = vec![PartialOp::Limited(3), PartialOp::AlignedTo(4)];
+    /// let mut partial_writer = CoalescingPartialAsyncWrite::new(Cursor::new(Vec::new()), ops);
+    /// partial_writer.verify_monotonic();
+    /// partial_writer.write_all(b"Hello, world!").unwrap();
+    /// partial_writer.flush().unwrap();
+    /// assert_eq!(&partial_writer.get_ref().get_ref()[..], b"Hello, world!");
+    /// ```
+    ///
+    /// [`shuffle_on_flush`]: #method.shuffle_on_flush
+    pub fn verify_monotonic(&mut self) {
+        let mut monotonic = Monotonic::with_contents();
+        monotonic.buffered(self.buffer());
+        self.monotonic = Some(monotonic);
+    }
+
+    /// Returns the data that has been written but not yet flushed to the
+    /// underlying writer.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf[self.pos..]
+    }
+
+    /// Returns a snapshot of the counters kept by this wrapper.
+    ///
+    /// See [`Metrics`](struct.Metrics.html) for more.
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.clone()
+    }
+
+    /// Returns the number of calls that reached the underlying writer.
+    ///
+    /// See [`Metrics::inner_io_calls`](struct.Metrics.html#structfield.inner_io_calls)
+    /// for more.
+    pub fn inner_io_calls(&self) -> usize {
+        self.metrics.inner_io_calls
+    }
+
+    /// Starts recording the size of the buffer passed into each `write` call.
+    ///
+    /// The sizes are returned by [`inner_write_sizes`]. Nothing is recorded
+    /// unless this is called, so that long streams don't build up an
+    /// ever-growing list.
+    ///
+    /// [`inner_write_sizes`]: #method.inner_write_sizes
+    pub fn record_write_sizes(&mut self) {
+        if self.write_sizes.is_none() {
+            self.write_sizes = Some(Vec::new());
+        }
+    }
+
+    /// Returns the size of the buffer passed into each `write` call since
+    /// [`record_write_sizes`] was called, in order.
+    ///
+    /// This records what the code under test asked to write, before any
+    /// operations are applied. It is useful for checking how calls are
+    /// batched: for example, that a buffered writer wrote `[4096, 4096, 128]`.
+    /// Returns an empty slice if sizes aren't being recorded.
+    ///
+    /// [`record_write_sizes`]: #method.record_write_sizes
+    pub fn inner_write_sizes(&self) -> &[usize] {
+        match self.write_sizes {
+            Some(ref sizes) => sizes,
+            None => &[],
+        }
+    }
+
+    /// Returns whether the most recent call applied an operation from the
+    /// iterator, or passed straight through to the underlying writer.
+    ///
+    /// Returns `None` if no calls have been made yet. See
+    /// [`Metrics::last_op_injected`](struct.Metrics.html#structfield.last_op_injected)
+    /// for more.
+    pub fn last_op_was_injected(&self) -> Option<bool> {
+        self.metrics.last_op_injected
+    }
+
+    /// Acquires a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Acquires a mutable reference to the underlying writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Consumes this wrapper, returning the underlying writer.
+    ///
+    /// Any data that has not been flushed is discarded.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// Appends `len` to the recorded write sizes, if recording.
+    fn record_write_size(&mut self, len: usize) {
+        if let Some(sizes) = self.write_sizes.as_mut() {
+            sizes.push(len);
+        }
+    }
+
+    /// Shuffles the bytes that have been buffered since the last shuffle, if
+    /// enabled.
+    fn shuffle_buffered(&mut self) {
+        if let Some(rng) = self.shuffle.as_mut() {
+            // Fisher-Yates, over the range that hasn't been shuffled yet.
+            let unshuffled = &mut self.buf[self.shuffled..];
+            for i in (1..unshuffled.len()).rev() {
+                unshuffled.swap(i, rng.below(i + 1));
+            }
+            if let Some(monotonic) = self.monotonic.as_mut() {
+                monotonic.reordered(&self.buf[self.pos..]);
+            }
+        }
+        self.shuffled = self.buf.len();
+    }
+
+    /// Checks bytes from the buffer that the underlying writer accepted against
+    /// the monotonic position, if verifying.
+    fn forwarded(&mut self, res: &io::Result<usize>) {
+        if let (Some(monotonic), &Ok(n)) = (self.monotonic.as_mut(), res) {
+            let unforwarded = (self.buf.len() - self.pos) as u64;
+            let offset = monotonic.accepted_len() - unforwarded;
+            // The underlying writer might report more bytes than it was given.
+            let end = cmp::min(self.pos + n, self.buf.len());
+            monotonic.forwarded(offset, &self.buf[self.pos..end]);
+        }
+    }
+
+    fn write_buffered(&mut self) -> io::Result<usize> {
+        let buf = &self.buf[self.pos..];
+        let op = self.metrics.op(self.iter.next().map(PartialOp::for_write));
+        let res = match op {
+            Some(PartialOp::Limited(n)) => {
+                let len = cmp::min(n, buf.len());
+                self.metrics.drained(self.inner.write(&buf[..len]))
+            }
+            Some(PartialOp::AlignedTo(align)) => {
+                let len = aligned_len(buf.len(), align);
+                if len == 0 {
+                    Err(self.metrics.injected(io::ErrorKind::InvalidInput,
+                                              OpKind::Flush,
+                                              "buffer smaller than alignment during flush, \
+                                               generated by partial-io"))
+                } else {
+                    self.metrics.drained(self.inner.write(&buf[..len]))
+                }
+            }
+            Some(PartialOp::Err(err)) => {
+                if err == io::ErrorKind::WouldBlock {
+                    // Make sure this task is rechecked.
+                    task::current().notify();
+                }
+                Err(self.metrics.injected(err,
+                                          OpKind::Flush,
+                                          "error during flush, generated by partial-io"))
+            }
+            Some(PartialOp::VectoredLimited(ref caps)) => {
+                let len = cmp::min(caps.first().cloned().unwrap_or(0), buf.len());
+                self.metrics.drained(self.inner.write(&buf[..len]))
+            }
+            Some(PartialOp::AsyncWriteZero) => {
+                Err(self.metrics.injected(io::ErrorKind::WriteZero,
+                                          OpKind::Flush,
+                                          "write accepted no data during flush, \
+                                           generated by partial-io"))
+            }
+            // Only PartialAsyncWrite supports this.
+            Some(PartialOp::DropAfter(_)) |
+            // Only PartialWrite supports this.
+            Some(PartialOp::ErrAfterFlush(_)) |
+            // Direction-specific operations have already been resolved.
+            Some(PartialOp::Duplicate) |
+            Some(PartialOp::ErrOnLast(_)) |
+            Some(PartialOp::ReadLimited(_)) |
+            Some(PartialOp::WriteLimited(_)) |
+            Some(PartialOp::ReadErr(_)) |
+            Some(PartialOp::WriteErr(_)) |
+            Some(PartialOp::Unlimited) |
+            Some(PartialOp::ReportExactly(_)) |
+            None => self.metrics.drained(self.inner.write(buf)),
+        };
+        trace_op(OpKind::Flush, &self.metrics, &op, Some(buf.len()), &res);
+        self.forwarded(&res);
+        res
+    }
+
+    /// Drains the buffer into the inner writer, then flushes it.
+    fn flush_buffered(&mut self) -> io::Result<()> {
+        self.shuffle_buffered();
+        while self.pos < self.buf.len() {
+            match self.write_buffered()? {
+                0 => {
+                    let err = PartialIoError::not_injected(OpKind::Flush,
+                                                           "inner writer accepted no data \
+                                                            during flush");
+                    return Err(io::Error::new(io::ErrorKind::WriteZero, err));
+                }
+                n => self.pos = cmp::min(self.pos + n, self.buf.len()),
+            }
+        }
+        if let Some(monotonic) = self.monotonic.as_ref() {
+            monotonic.check_forwarded();
+        }
+        self.buf.clear();
+        self.pos = 0;
+        self.shuffled = 0;
+        self.metrics.inner(self.inner.flush())
+    }
+}
+
+impl<W, I> Write for CoalescingPartialAsyncWrite<W, I>
+    where W: AsyncWrite,
+          I: IntoIterator<Item = PartialOp>
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.metrics.call(buf.len());
+        self.record_write_size(buf.len());
+        self.buf.extend_from_slice(buf);
+        if let Some(monotonic) = self.monotonic.as_mut() {
+            monotonic.buffered(buf);
+        }
+        let res = self.metrics.buffered(buf.len());
+        trace_op(OpKind::Write, &self.metrics, &None, Some(buf.len()), &res);
+        res
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.metrics.flush();
+        let res = self.flush_buffered();
+        trace_op(OpKind::Flush, &self.metrics, &None, None, &res);
+        res
+    }
+}
+
+impl<W, I> AsyncWrite for CoalescingPartialAsyncWrite<W, I>
+    where W: AsyncWrite,
+          I: IntoIterator<Item = PartialOp>
+{
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        match self.flush() {
+            Ok(()) => self.inner.shutdown(),
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+            Err(err) => Err(err),
+        }
+    }
+}
@@ -0,0 +1,131 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! This module contains the error type carried by errors that partial-io
+//! generates itself.
+
+use std::error;
+use std::fmt;
+use std::io;
+
+use OpKind;
+
+/// The error inside every `io::Error` generated by a wrapper, as opposed to
+/// one returned by the underlying reader or writer.
+///
+/// Wrappers return plain `io::Error`s so that they can stand in for any reader
+/// or writer, but the errors they inject carry a `PartialIoError` as their
+/// inner error. This makes it possible to reliably tell injected faults apart
+/// from real ones, for example to check that a retry loop only ever saw
+/// injected errors.
+///
+/// A few errors are generated by a wrapper without being injected faults,
+/// like the one from `PartialAsyncWrite::finish` when operations are left
+/// over. These carry a `PartialIoError` too, but [`is_injected`] returns
+/// `false` for them.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::{self, Write};
+///
+/// use partial_io::{OpKind, PartialIoError, PartialOp, PartialWrite};
+///
+/// let ops = vec![PartialOp::Limited(2), PartialOp::Err(io::ErrorKind::Interrupted)];
+/// let mut partial_writer = PartialWrite::new(Vec::new(), ops);
+/// partial_writer.write(b"Hello").unwrap();
+///
+/// let err = partial_writer.write(b"llo").unwrap_err();
+/// let injected = PartialIoError::from_io(&err).unwrap();
+/// assert_eq!(injected.op_kind(), OpKind::Write);
+/// assert_eq!(injected.op_index(), Some(1));
+/// assert!(injected.is_injected());
+///
+/// // Errors from the underlying writer don't carry a PartialIoError.
+/// let real = io::Error::new(io::ErrorKind::Interrupted, "interrupted");
+/// assert!(PartialIoError::from_io(&real).is_none());
+/// ```
+///
+/// [`is_injected`]: #method.is_injected
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PartialIoError {
+    op_kind: OpKind,
+    op_index: Option<usize>,
+    message: &'static str,
+    injected: bool,
+}
+
+impl PartialIoError {
+    pub(crate) fn new(op_kind: OpKind,
+                      op_index: Option<usize>,
+                      message: &'static str)
+                      -> Self {
+        PartialIoError {
+            op_kind,
+            op_index,
+            message,
+            injected: true,
+        }
+    }
+
+    /// Creates an error that a wrapper reports about the code under test or
+    /// the underlying writer, rather than injects.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn not_injected(op_kind: OpKind, message: &'static str) -> Self {
+        PartialIoError {
+            op_kind,
+            op_index: None,
+            message,
+            injected: false,
+        }
+    }
+
+    /// Returns the `PartialIoError` inside `err`, if it was generated by a
+    /// wrapper.
+    ///
+    /// This is a shorthand for
+    /// `err.get_ref().and_then(|e| e.downcast_ref::<PartialIoError>())`.
+    pub fn from_io(err: &io::Error) -> Option<&PartialIoError> {
+        err.get_ref().and_then(|e| e.downcast_ref::<PartialIoError>())
+    }
+
+    /// Returns the kind of call that the error was returned from.
+    pub fn op_kind(&self) -> OpKind {
+        self.op_kind
+    }
+
+    /// Returns the index, in the wrapper's iterator, of the operation that
+    /// generated the error.
+    ///
+    /// This is the same index that `Metrics::ops_consumed` was at just before
+    /// the operation was taken. It is `None` for errors caused by the state of
+    /// the wrapper rather than by an operation, like a full watermark.
+    pub fn op_index(&self) -> Option<usize> {
+        self.op_index
+    }
+
+    /// Returns whether the error is a fault injected by partial-io.
+    ///
+    /// This is `false` for errors that a wrapper generates to report a problem
+    /// instead, like `PartialAsyncWrite::finish` finding operations left over,
+    /// or `CoalescingPartialAsyncWrite` flushing into an underlying writer
+    /// that accepts no data. Errors from the underlying reader or writer never
+    /// carry a `PartialIoError` at all.
+    pub fn is_injected(&self) -> bool {
+        self.injected
+    }
+}
+
+impl fmt::Display for PartialIoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.message)
+    }
+}
+
+impl error::Error for PartialIoError {}
@@ -0,0 +1,193 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! This module contains functions that generate sequences of `PartialOp`s
+//! following particular profiles.
+
+use std::io;
+use std::iter::{self, Repeat};
+
+#[cfg(feature = "rand")]
+use rand::Rng;
+
+use PartialOp;
+use rng::XorShift;
+
+/// Generates `len` operations modeling a connection that degrades over time.
+///
+/// Each operation is either `PartialOp::Unlimited` or a
+/// `PartialOp::Err(io::ErrorKind::WouldBlock)`. The probability of a
+/// `WouldBlock` interpolates linearly from `start_prob` for the first
+/// operation to `end_prob` for the last one, so a transfer of known length
+/// starts out healthy and gets progressively worse. This is useful for
+/// testing graceful degradation and reconnection logic.
+///
+/// The same `seed` always generates the same operations.
+///
+/// # Panics
+///
+/// Panics if `start_prob` or `end_prob` is not between 0 and 1.
+///
+/// # Examples
+///
+/// ```rust
+/// use partial_io::{PartialOp, degrading};
+///
+/// let ops = degrading(100, 0.0, 1.0, 42);
+/// assert_eq!(ops.len(), 100);
+/// // The first operation never fails, and the last one always does.
+/// assert_eq!(format!("{:?}", ops[0]), "Unlimited");
+/// assert_eq!(format!("{:?}", ops[99]), "Err(WouldBlock)");
+/// ```
+pub fn degrading(len: usize, start_prob: f64, end_prob: f64, seed: u64) -> Vec<PartialOp> {
+    assert!((0.0..=1.0).contains(&start_prob),
+            "start_prob must be between 0 and 1");
+    assert!((0.0..=1.0).contains(&end_prob),
+            "end_prob must be between 0 and 1");
+
+    let mut rng = XorShift::new(seed);
+    (0..len)
+        .map(|i| {
+            let progress = if len > 1 {
+                i as f64 / (len - 1) as f64
+            } else {
+                0.0
+            };
+            let prob = start_prob + (end_prob - start_prob) * progress;
+            if rng.next_f64() < prob {
+                PartialOp::Err(io::ErrorKind::WouldBlock)
+            } else {
+                PartialOp::Unlimited
+            }
+        })
+        .collect()
+}
+
+/// Returns an endless sequence of `PartialOp::Err(io::ErrorKind::Interrupted)`.
+///
+/// This is for checking that a retry loop gives up eventually instead of
+/// hanging when the underlying reader or writer is persistently interrupted.
+/// A wrapper using these operations never makes progress, so code under test
+/// must bound its retries to get past it.
+///
+/// Note that `Read::read_to_end`, `Read::read_exact`, `Write::write_all` and
+/// similar helpers in the standard library retry `Interrupted` errors without
+/// limit, so using them with this *will* hang. That is intended: this finds
+/// unbounded loops in your code, and the standard library's are unbounded by
+/// design.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::{self, Cursor, Read};
+///
+/// use partial_io::{PartialRead, always_interrupted};
+///
+/// fn read_with_retries<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+///     for _ in 0..3 {
+///         match reader.read(buf) {
+///             Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+///             res => return res,
+///         }
+///     }
+///     Err(io::Error::new(io::ErrorKind::TimedOut, "too many interruptions"))
+/// }
+///
+/// let mut partial_reader = PartialRead::new(Cursor::new(vec![1, 2, 3]), always_interrupted());
+/// let err = read_with_retries(&mut partial_reader, &mut [0; 3]).unwrap_err();
+/// assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+/// ```
+pub fn always_interrupted() -> Repeat<PartialOp> {
+    iter::repeat(PartialOp::Err(io::ErrorKind::Interrupted))
+}
+
+/// Returns a curated set of the shortest operation sequences that exercise
+/// particular edge cases, each with a short name.
+///
+/// This is a standard smoke-test battery: looping over every entry and running
+/// the code under test with each one covers the common ways that partial IO
+/// goes wrong, with the smallest possible sequence for each. The entries are
+/// fixed and not random, and more may be added over time. They are:
+///
+/// * `"short"`: a single transfer of one byte.
+/// * `"would-block"`: a single `WouldBlock` error.
+/// * `"interrupted"`: a single `Interrupted` error.
+/// * `"mid-stream-error"`: a transfer of one byte followed by a fatal error,
+///   for checking that progress made before an error isn't lost.
+/// * `"eof-then-error"`: a transfer of zero bytes followed by a fatal error.
+///   Readers see this as the end of the stream, so this checks that code
+///   doesn't keep reading past it. Writers see a write that accepts nothing.
+///
+/// Once an entry's operations run out, the wrapper passes calls through.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::{self, Write};
+///
+/// use partial_io::{PartialWrite, adversarial_minimals};
+///
+/// for (name, ops) in adversarial_minimals() {
+///     let mut partial_writer = PartialWrite::new(Vec::new(), ops);
+///     match partial_writer.write_all(b"Hello, world!") {
+///         Ok(()) => assert_eq!(&partial_writer.get_ref()[..], b"Hello, world!", "{}", name),
+///         Err(err) => assert_ne!(err.kind(), io::ErrorKind::Interrupted, "{}", name),
+///     }
+/// }
+/// ```
+pub fn adversarial_minimals() -> Vec<(&'static str, Vec<PartialOp>)> {
+    vec![("short", vec![PartialOp::Limited(1)]),
+         ("would-block", vec![PartialOp::Err(io::ErrorKind::WouldBlock)]),
+         ("interrupted", vec![PartialOp::Err(io::ErrorKind::Interrupted)]),
+         ("mid-stream-error", vec![PartialOp::Limited(1), PartialOp::Err(io::ErrorKind::Other)]),
+         ("eof-then-error", vec![PartialOp::Limited(0), PartialOp::Err(io::ErrorKind::Other)])]
+}
+
+/// Generates `len` `PartialOp::Limited` operations, with limits drawn by
+/// `sample`.
+///
+/// `sample` is called once per operation with `rng`, and returns the limit to
+/// use. This allows for any distribution of sizes, like the long-tailed ones
+/// seen on real sockets, where most transfers are large and a few are small.
+/// Distributions from crates like `rand_distr` can be plugged in with
+/// `|rng| dist.sample(rng)`. Seeding `rng` makes the operations reproducible.
+///
+/// Note that a limit of 0 makes readers signal the end of the stream, so
+/// `sample` should usually avoid returning 0.
+///
+/// Available with the `rand` feature.
+///
+/// # Examples
+///
+/// ```rust
+/// extern crate partial_io;
+/// extern crate rand;
+///
+/// use rand::{Rng, SeedableRng};
+/// use rand::rngs::StdRng;
+///
+/// use partial_io::sampled_limits;
+///
+/// fn main() {
+///     // Exponentially distributed limits with a mean of about 1024 bytes.
+///     let mut rng = StdRng::seed_from_u64(42);
+///     let ops = sampled_limits(100, &mut rng, |rng| {
+///         let uniform: f64 = rng.gen();
+///         (-(1.0 - uniform).ln() * 1024.0) as usize + 1
+///     });
+///     assert_eq!(ops.len(), 100);
+/// }
+/// ```
+#[cfg(feature = "rand")]
+pub fn sampled_limits<R, F>(len: usize, rng: &mut R, mut sample: F) -> Vec<PartialOp>
+    where R: Rng,
+          F: FnMut(&mut R) -> usize
+{
+    (0..len).map(|_| PartialOp::Limited(sample(rng))).collect()
+}
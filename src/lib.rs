@@ -20,9 +20,20 @@
 //!   [`PartialAsyncWrite`] to wrap existing `AsyncRead` and `AsyncWrite`
 //!   implementations. These implementations are task-aware, so they will know
 //!   how to pause and unpause tasks if they return a `WouldBlock` error.
+//!   [`CoalescingPartialAsyncWrite`] additionally buffers writes and only
+//!   passes them on, broken up, when flushed.
 //! * With the optional `quickcheck` feature, generation of random sequences of
 //!   operations which can be fed into any of the wrapper. See the
 //!   [`quickcheck_types` documentation](quickcheck_types/index.html) for more.
+//! * With the optional `rand` feature, `sampled_limits` to generate limits
+//!   from any distribution of sizes.
+//! * With the optional `tracing` feature, a `tracing` event for every
+//!   operation performed by a wrapper, at the `TRACE` level. Events carry the
+//!   kind of call, the index and value of the operation, the buffer length
+//!   and the result as fields, and nest under the caller's current span.
+//! * Ready-made checks in the [`test` module](test/index.html), like asserting
+//!   that a writer adapter gets all its data through one byte at a time, or
+//!   that two writers produce the same bytes under the same operations.
 //!
 //! `partial-io` is particularly useful for `Read` and `Write` implementations
 //! with internal buffers, which can be fiddly to get right.
@@ -55,33 +66,65 @@
 //! [`PartialWrite`]: struct.PartialWrite.html
 //! [`PartialAsyncRead`]: struct.PartialAsyncRead.html
 //! [`PartialAsyncWrite`]: struct.PartialAsyncWrite.html
+//! [`CoalescingPartialAsyncWrite`]: struct.CoalescingPartialAsyncWrite.html
 
 #[cfg(feature = "tokio")]
 extern crate futures;
 #[cfg(feature = "quickcheck")]
 extern crate quickcheck;
+#[cfg(feature = "rand")]
+extern crate rand;
 #[cfg(feature = "tokio")]
 extern crate tokio_io;
+#[cfg(feature = "tracing")]
+#[macro_use]
+extern crate tracing;
 
 #[cfg(feature = "tokio")]
 mod async_read;
 #[cfg(feature = "tokio")]
 mod async_write;
+#[cfg(feature = "tokio")]
+mod coalescing_async_write;
+mod error;
+mod generators;
+mod metrics;
+mod monotonic;
+mod op_iter;
 #[cfg(feature = "quickcheck")]
 pub mod quickcheck_types;
+mod pattern;
 mod read;
+mod rng;
+mod segments;
+pub mod test;
+mod utf8;
 mod write;
 
-use std::io;
+use std::cmp;
+use std::io::{self, IoSlice};
+use std::panic::UnwindSafe;
 
 #[cfg(feature = "quickcheck")]
-pub use quickcheck_types::{GenError, GenInterrupted, GenInterruptedWouldBlock, GenNoErrors,
-                           GenWouldBlock, PartialWithErrors};
+pub use quickcheck_types::{ErrorWeights, GenError, GenErrorBuilder, GenInterrupted,
+                           GenInterruptedWouldBlock, GenNoErrors, GenWeighted, GenWouldBlock,
+                           PartialWithErrors, WeightedErrors, WeightsError};
+#[cfg(feature = "tokio")]
+pub use async_read::{PartialAsyncRead, PollResult};
 #[cfg(feature = "tokio")]
-pub use async_read::PartialAsyncRead;
+pub use async_write::{Finish, PartialAsyncWrite};
 #[cfg(feature = "tokio")]
-pub use async_write::PartialAsyncWrite;
+pub use coalescing_async_write::CoalescingPartialAsyncWrite;
+pub use error::PartialIoError;
+pub use generators::{adversarial_minimals, always_interrupted, degrading};
+#[cfg(feature = "rand")]
+pub use generators::sampled_limits;
+pub use metrics::Metrics;
+pub use op_iter::{CappedAt, ErrorsEvery, PartialOpIterExt};
+pub use pattern::{DEFAULT_OPS_ENV, PatternError, ops_from_pattern};
 pub use read::PartialRead;
+pub use segments::Segments;
+pub use utf8::utf8_split_ops;
 pub use write::PartialWrite;
 
 /// What to do the next time an IO operation is performed.
@@ -96,6 +139,10 @@ pub enum PartialOp {
     /// The wrapper will call into the inner `Read` or `Write`
     /// instance. Depending on what the underlying operation does, this may
     /// return an error or a fewer number of bytes.
+    ///
+    /// For readers, `Limited(0)` makes `read` return `Ok(0)`, which callers
+    /// treat as the end of the stream. For example, `Read::chain` will move on
+    /// to the next reader even if the inner reader had more data.
     Limited(usize),
 
     /// Do not limit the next IO operation.
@@ -107,4 +154,356 @@ pub enum PartialOp {
 
     /// Return an error instead of calling into the underlying operation.
     Err(io::ErrorKind),
+
+    /// Round the next IO operation down to a multiple of the given alignment.
+    ///
+    /// This models block devices and `O_DIRECT` files, which only transfer
+    /// whole blocks. The buffer passed to the inner `Read` or `Write` instance
+    /// is truncated to the largest multiple of the alignment that fits in it.
+    ///
+    /// If the buffer is non-empty but smaller than the alignment, the wrapper
+    /// returns an `io::ErrorKind::InvalidInput` error without calling into the
+    /// underlying operation, much like `O_DIRECT` fails with `EINVAL`.
+    ///
+    /// # Panics
+    ///
+    /// The wrapper panics if the alignment is 0.
+    AlignedTo(usize),
+
+    /// Perform the next IO operation without limits, but report the given
+    /// number of bytes as having been transferred.
+    ///
+    /// This intentionally violates the `Read` and `Write` contracts: the count
+    /// returned may be more than the size of the buffer, or less than what was
+    /// actually read or written. It is meant for testing code that reconciles
+    /// reported counts against its own bookkeeping. Unlike `Limited`, which
+    /// caps what is actually transferred, this only changes what is reported.
+    ///
+    /// Errors from the underlying operation are returned as is.
+    ///
+    /// `CoalescingPartialAsyncWrite` treats this as `Unlimited`, since it
+    /// makes the inner calls itself.
+    ReportExactly(usize),
+
+    /// Like `Limited`, but only for reads.
+    ///
+    /// Writers treat this as `Unlimited`. This, along with `WriteLimited`,
+    /// `ReadErr` and `WriteErr`, lets one script describe both directions of a
+    /// test: clones of the same sequence can be given to a reader and a writer,
+    /// and each one only acts on the operations meant for it. An operation
+    /// meant for the other direction is still used up, so both wrappers stay
+    /// at the same position in the script.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::{Cursor, Read, Write};
+    ///
+    /// use partial_io::{PartialOp, PartialRead, PartialWrite};
+    ///
+    /// let ops = vec![PartialOp::ReadLimited(1), PartialOp::WriteLimited(2)];
+    /// let mut reader = PartialRead::new(Cursor::new(vec![1, 2, 3, 4]), ops.clone());
+    /// let mut writer = PartialWrite::new(Vec::new(), ops);
+    ///
+    /// let mut buf = [0; 4];
+    /// assert_eq!(reader.read(&mut buf).unwrap(), 1);
+    /// assert_eq!(reader.read(&mut buf).unwrap(), 3);
+    ///
+    /// assert_eq!(writer.write(&[1, 2, 3, 4]).unwrap(), 4);
+    /// assert_eq!(writer.write(&[1, 2, 3, 4]).unwrap(), 2);
+    /// ```
+    ReadLimited(usize),
+
+    /// Like `Limited`, but only for writes and flushes.
+    ///
+    /// Readers treat this as `Unlimited`. See `ReadLimited` for more.
+    WriteLimited(usize),
+
+    /// Like `Err`, but only for reads.
+    ///
+    /// Writers treat this as `Unlimited`. See `ReadLimited` for more.
+    ReadErr(io::ErrorKind),
+
+    /// Like `Err`, but only for writes and flushes.
+    ///
+    /// Readers treat this as `Unlimited`. See `ReadLimited` for more.
+    WriteErr(io::ErrorKind),
+
+    /// Make the next write return `Ok(0)` without calling the inner writer.
+    ///
+    /// Returning `Ok(0)` for a non-empty buffer is how a writer signals that
+    /// the sink won't accept any more data, for example because it is full or
+    /// has been closed. This is different from `Err(WouldBlock)`, which means
+    /// the writer isn't ready yet and the task will be woken up later: code
+    /// that retries after `Ok(0)` will spin forever. Well-behaved callers turn
+    /// it into an error instead, like `write_all` does with `WriteZero`.
+    ///
+    /// This has the same effect on synchronous and asynchronous writers, and
+    /// an empty buffer also gets `Ok(0)`. `CoalescingPartialAsyncWrite` fails
+    /// a flush with `WriteZero` when it sees this. Readers and flushes treat
+    /// this as `Unlimited`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::{self, Write};
+    ///
+    /// use partial_io::{PartialOp, PartialWrite};
+    ///
+    /// let mut partial_writer = PartialWrite::new(Vec::new(), vec![PartialOp::AsyncWriteZero]);
+    /// let err = partial_writer.write_all(b"Hello").unwrap_err();
+    /// assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+    /// ```
+    AsyncWriteZero,
+
+    /// Let writes through until a total of `n` bytes have been written, then
+    /// drop the connection.
+    ///
+    /// This models a peer that vanishes in the middle of a transfer. Once this
+    /// operation is taken, writes pass through to the inner writer, cut short
+    /// where necessary so that no more than `n` bytes are written by the
+    /// wrapper in total, counting bytes written before this operation. After
+    /// that, the connection is gone for good: every later `write`, `flush` and
+    /// `shutdown` returns a `ConnectionReset` error. No further operations are
+    /// taken from the iterator once this one has been.
+    ///
+    /// Only `PartialAsyncWrite` supports this. Other writers and readers treat
+    /// it as `Unlimited`.
+    DropAfter(usize),
+
+    /// Let the next write through, then fail the first write after the next
+    /// successful flush with the given error.
+    ///
+    /// Once this operation is taken, the error is armed. Writes keep going as
+    /// usual until a `flush` succeeds, and then the next `write` or
+    /// `write_vectored` call returns the error without taking an operation or
+    /// calling the inner writer. If this operation is taken by a `flush`, that
+    /// flush counts as the successful flush if it succeeds. The error only
+    /// fires once, and taking another `ErrAfterFlush` while one is armed
+    /// replaces it. Flushes made while fault injection is disabled don't
+    /// count, and neither does the error fire while disabled.
+    ///
+    /// This targets code that assumes a successful flush means the data is
+    /// durable, or that the connection is still good afterwards.
+    ///
+    /// Only `PartialWrite` supports this. Other writers and readers treat it
+    /// as `Unlimited`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::{self, Write};
+    ///
+    /// use partial_io::{PartialOp, PartialWrite};
+    ///
+    /// let ops = vec![PartialOp::ErrAfterFlush(io::ErrorKind::BrokenPipe)];
+    /// let mut partial_writer = PartialWrite::new(Vec::new(), ops);
+    ///
+    /// partial_writer.write_all(b"Hello, ").unwrap();
+    /// partial_writer.write_all(b"world!").unwrap();
+    /// partial_writer.flush().unwrap();
+    /// let err = partial_writer.write(b"\n").unwrap_err();
+    /// assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    /// assert_eq!(partial_writer.write(b"\n").unwrap(), 1);
+    /// ```
+    ErrAfterFlush(io::ErrorKind),
+
+    /// Limit each buffer passed to the next `write_vectored` call separately.
+    ///
+    /// The `i`th buffer is cut down to at most `caps[i]` bytes, and buffers
+    /// past the end of `caps` are left out. Since a writer can't skip over
+    /// bytes, everything after the first buffer that was cut short is left out
+    /// too. So `VectoredLimited(vec![usize::MAX, 0])` accepts the first
+    /// buffer in full and none of the second.
+    ///
+    /// The buffers that are left are passed to the inner writer's
+    /// `write_vectored` in a single call. Depending on what the inner writer
+    /// does, it may write even less. A plain `write` call, or a flush in
+    /// `CoalescingPartialAsyncWrite`, is limited to the first cap, or to 0
+    /// bytes if there are none. Readers treat this as `Unlimited`.
+    ///
+    /// This is useful for finding bugs in code that advances through a list of
+    /// buffers after a partial vectored write.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::{IoSlice, Write};
+    ///
+    /// use partial_io::{PartialOp, PartialWrite};
+    ///
+    /// let ops = vec![PartialOp::VectoredLimited(vec![3, 1, 4])];
+    /// let mut partial_writer = PartialWrite::new(Vec::new(), ops);
+    /// let bufs = [IoSlice::new(b"abc"), IoSlice::new(b"def"), IoSlice::new(b"ghi")];
+    ///
+    /// assert_eq!(partial_writer.write_vectored(&bufs).unwrap(), 4);
+    /// assert_eq!(partial_writer.get_ref(), b"abcd");
+    /// ```
+    VectoredLimited(Vec<usize>),
+
+    /// Make the next read return the same bytes as the previous one, without
+    /// calling the inner reader.
+    ///
+    /// This is an intentional content fault, modeling a buggy source that
+    /// retransmits data. It is meant for testing deduplication and idempotency
+    /// logic, which should notice the repeated bytes. The position of the
+    /// inner reader doesn't change, so the read after a duplicate picks up
+    /// where the previous one left off.
+    ///
+    /// If the buffer is smaller than the previous read, only the start of the
+    /// previous bytes is returned. Only the bytes that were actually read are
+    /// repeated, even if the previous read reported a different count with
+    /// `ReportExactly`. If the previous read returned no bytes, for example
+    /// because it failed, this is treated as `Unlimited`. Consecutive
+    /// duplicates return the same bytes. Writers treat this as `Unlimited`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::{Cursor, Read};
+    ///
+    /// use partial_io::{PartialOp, PartialRead};
+    ///
+    /// let ops = vec![PartialOp::Limited(2), PartialOp::Duplicate];
+    /// let mut partial_reader = PartialRead::new(Cursor::new(b"abcd".to_vec()), ops);
+    ///
+    /// let mut out = Vec::new();
+    /// partial_reader.read_to_end(&mut out).unwrap();
+    /// assert_eq!(&out, b"ababcd");
+    /// ```
+    Duplicate,
+
+    /// Fail with an error of this kind if the read would return the last bytes
+    /// of the inner reader or signal the end of the stream, and otherwise
+    /// behave like `Unlimited`.
+    ///
+    /// This is for hunting off-by-one bugs in end-of-stream handling. "Last"
+    /// is only well-defined if the length of the inner reader is known, so
+    /// this only has an effect on readers created with
+    /// [`PartialRead::with_len`]. A read "would return the last bytes" if its
+    /// buffer is at least as large as what is left in the inner reader. The
+    /// inner reader isn't called, so retrying the read with the next operation
+    /// picks up where the previous one left off.
+    ///
+    /// Other readers and writers treat this as `Unlimited`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::{self, Cursor, Read};
+    ///
+    /// use partial_io::{PartialOp, PartialRead};
+    ///
+    /// let ops = vec![PartialOp::ErrOnLast(io::ErrorKind::UnexpectedEof); 2];
+    /// let mut partial_reader = PartialRead::with_len(Cursor::new(b"abcd".to_vec()), 4, ops);
+    ///
+    /// // This leaves a byte in the inner reader, so it goes through.
+    /// assert_eq!(partial_reader.read(&mut [0; 3]).unwrap(), 3);
+    /// let err = partial_reader.read(&mut [0; 3]).unwrap_err();
+    /// assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    /// // The operations have run out, so this read goes through.
+    /// assert_eq!(partial_reader.read(&mut [0; 3]).unwrap(), 1);
+    /// ```
+    ///
+    /// [`PartialRead::with_len`]: struct.PartialRead.html#method.with_len
+    ErrOnLast(io::ErrorKind),
+}
+
+impl PartialOp {
+    /// Resolves direction-specific operations as seen by a reader.
+    fn for_read(self) -> Self {
+        match self {
+            PartialOp::ReadLimited(n) => PartialOp::Limited(n),
+            PartialOp::ReadErr(err) => PartialOp::Err(err),
+            PartialOp::WriteLimited(_) |
+            PartialOp::WriteErr(_) |
+            PartialOp::AsyncWriteZero |
+            PartialOp::DropAfter(_) |
+            PartialOp::ErrAfterFlush(_) |
+            PartialOp::VectoredLimited(_) => PartialOp::Unlimited,
+            op => op,
+        }
+    }
+
+    /// Resolves direction-specific operations as seen by a writer.
+    fn for_write(self) -> Self {
+        match self {
+            PartialOp::WriteLimited(n) => PartialOp::Limited(n),
+            PartialOp::WriteErr(err) => PartialOp::Err(err),
+            PartialOp::ReadLimited(_) |
+            PartialOp::ReadErr(_) |
+            PartialOp::Duplicate |
+            PartialOp::ErrOnLast(_) => PartialOp::Unlimited,
+            op => op,
+        }
+    }
+}
+
+/// The kind of call a wrapper received.
+///
+/// This is reported by [`PartialIoError`](struct.PartialIoError.html).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OpKind {
+    /// A `read` call.
+    Read,
+    /// A `write` call.
+    Write,
+    /// A `flush` call. `CoalescingPartialAsyncWrite` also reports the inner
+    /// writes it makes while flushing as `Flush`.
+    Flush,
+}
+
+/// A callback passed every injected error returned by a wrapper.
+///
+/// This is `Sync` and `UnwindSafe` so that the wrappers holding it stay so.
+type ErrorCallback = Box<dyn FnMut(&io::Error) + Send + Sync + UnwindSafe>;
+
+/// Records a call made on a wrapper, if `tracing` is enabled.
+#[cfg(feature = "tracing")]
+fn trace_op<T: ::std::fmt::Debug>(kind: OpKind,
+                                  metrics: &Metrics,
+                                  op: &Option<PartialOp>,
+                                  buf_len: Option<usize>,
+                                  res: &io::Result<T>) {
+    let op_index = if op.is_some() {
+        Some(metrics.ops_consumed - 1)
+    } else {
+        None
+    };
+    trace!(target: "partial_io",
+           kind = ?kind,
+           op_index = ?op_index,
+           op = ?op,
+           buf_len = ?buf_len,
+           result = ?res,
+           "partial-io operation");
+}
+
+#[cfg(not(feature = "tracing"))]
+#[inline(always)]
+fn trace_op<T>(_kind: OpKind,
+               _metrics: &Metrics,
+               _op: &Option<PartialOp>,
+               _buf_len: Option<usize>,
+               _res: &io::Result<T>) {
+}
+
+/// Cuts each of `bufs` down to the corresponding entry in `caps`, stopping
+/// after the first buffer that was cut short.
+fn capped_slices<'a>(bufs: &'a [IoSlice], caps: &[usize]) -> Vec<IoSlice<'a>> {
+    let mut capped = Vec::new();
+    for (buf, &cap) in bufs.iter().zip(caps) {
+        let len = cmp::min(cap, buf.len());
+        capped.push(IoSlice::new(&buf[..len]));
+        if len < buf.len() {
+            break;
+        }
+    }
+    capped
+}
+
+/// Returns the largest multiple of `align` that is no greater than `len`.
+fn aligned_len(len: usize, align: usize) -> usize {
+    assert!(align > 0, "PartialOp::AlignedTo requires a non-zero alignment");
+    len / align * align
 }
@@ -0,0 +1,188 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! This module contains the counters that every wrapper keeps track of.
+
+use std::cmp;
+use std::io;
+
+use {OpKind, PartialIoError, PartialOp};
+
+/// A snapshot of the counters kept by a wrapper.
+///
+/// This is returned by the `metrics` method on every wrapper. It is a copy of
+/// the counters at the time `metrics` was called, not a live view: later
+/// operations on the wrapper will not be reflected in it. This makes it
+/// convenient to log at the end of a test, or to compare two runs against each
+/// other.
+///
+/// For the async wrappers, every poll counts as a call.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::{self, Write};
+///
+/// use partial_io::{PartialOp, PartialWrite};
+///
+/// let ops = vec![PartialOp::Limited(2), PartialOp::Err(io::ErrorKind::Interrupted)];
+/// let mut partial_writer = PartialWrite::new(Vec::new(), ops);
+/// partial_writer.write_all(&[1, 2, 3, 4]).unwrap();
+///
+/// let metrics = partial_writer.metrics();
+/// assert_eq!(metrics.ops_consumed, 2);
+/// assert_eq!(metrics.calls, 3);
+/// assert_eq!(metrics.bytes, 4);
+/// assert_eq!(metrics.errors_injected, 1);
+/// assert_eq!(metrics.max_buf_len, 4);
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Metrics {
+    /// The number of operations taken from the iterator so far. This is also
+    /// the index of the next operation to be taken.
+    pub ops_consumed: usize,
+    /// The number of `read` or `write` calls made on the wrapper.
+    pub calls: usize,
+    /// The number of `flush` calls made on the wrapper.
+    pub flushes: usize,
+    /// The number of bytes successfully read or written.
+    pub bytes: usize,
+    /// The number of errors generated by the wrapper.
+    pub errors_injected: usize,
+    /// The number of errors returned by the underlying reader or writer.
+    pub errors_inner: usize,
+    /// The size of the largest buffer passed into `read` or `write`.
+    pub max_buf_len: usize,
+    /// The largest number of bytes read or written by a single call.
+    pub max_transfer: usize,
+    /// The number of times a reader returned `Ok(0)` for a non-empty buffer,
+    /// signaling the end of the stream. This is always 0 for writers.
+    pub eofs: usize,
+    /// The number of `read` or `write` calls that reached the underlying
+    /// reader or writer.
+    ///
+    /// Calls that were answered by the wrapper itself, like injected errors or
+    /// `WouldBlock`, aren't counted, and neither are flushes. For the async
+    /// wrappers, this tells apart polls that touched the underlying socket
+    /// from ones that returned early without doing any IO.
+    pub inner_io_calls: usize,
+    /// Whether the most recent call took an explicit operation from the
+    /// iterator, or `None` if no calls have been made yet.
+    ///
+    /// `PartialOp::Unlimited` and an exhausted iterator count as passthrough.
+    /// Every other operation counts as injected, even if it ended up not
+    /// changing the outcome of the call (for example, a `Limited(n)` larger
+    /// than the buffer).
+    pub last_op_injected: Option<bool>,
+}
+
+impl Metrics {
+    pub(crate) fn call(&mut self, buf_len: usize) {
+        self.calls += 1;
+        self.max_buf_len = cmp::max(self.max_buf_len, buf_len);
+    }
+
+    pub(crate) fn flush(&mut self) {
+        self.flushes += 1;
+    }
+
+    pub(crate) fn op(&mut self, op: Option<PartialOp>) -> Option<PartialOp> {
+        if op.is_some() {
+            self.ops_consumed += 1;
+        }
+        self.last_op_injected = Some(match op {
+            Some(PartialOp::Unlimited) | None => false,
+            Some(_) => true,
+        });
+        op
+    }
+
+    pub(crate) fn injected(&mut self,
+                           kind: io::ErrorKind,
+                           op_kind: OpKind,
+                           message: &'static str)
+                           -> io::Error {
+        self.errors_injected += 1;
+        let op_index = Some(self.ops_consumed - 1);
+        io::Error::new(kind, PartialIoError::new(op_kind, op_index, message))
+    }
+
+    pub(crate) fn limit_exceeded(&mut self,
+                                 max_ops: Option<usize>,
+                                 op_kind: OpKind)
+                                 -> Option<io::Error> {
+        match max_ops {
+            Some(max_ops) if self.calls + self.flushes > max_ops => {
+                Some(self.injected_by_wrapper(io::ErrorKind::Other,
+                                              op_kind,
+                                              "partial-io operation limit exceeded"))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns an error made up by the wrapper without taking an operation,
+    /// like one from a watermark, and records the call as injected.
+    pub(crate) fn injected_by_wrapper(&mut self,
+                                      kind: io::ErrorKind,
+                                      op_kind: OpKind,
+                                      message: &'static str)
+                                      -> io::Error {
+        self.last_op_injected = Some(true);
+        self.injected_without_op(kind, op_kind, message)
+    }
+
+    fn injected_without_op(&mut self,
+                           kind: io::ErrorKind,
+                           op_kind: OpKind,
+                           message: &'static str)
+                           -> io::Error {
+        self.errors_injected += 1;
+        io::Error::new(kind, PartialIoError::new(op_kind, None, message))
+    }
+
+    pub(crate) fn read_returned(&mut self, buf_len: usize, res: &io::Result<usize>) {
+        if buf_len > 0 {
+            if let Ok(0) = *res {
+                self.eofs += 1;
+            }
+        }
+    }
+
+    pub(crate) fn inner<T>(&mut self, res: io::Result<T>) -> io::Result<T> {
+        if res.is_err() {
+            self.errors_inner += 1;
+        }
+        res
+    }
+
+    pub(crate) fn transferred(&mut self, res: io::Result<usize>) -> io::Result<usize> {
+        self.inner_io_calls += 1;
+        if let Ok(n) = res {
+            self.accepted(n);
+        }
+        self.inner(res)
+    }
+
+    pub(crate) fn buffered(&mut self, n: usize) -> io::Result<usize> {
+        self.accepted(n);
+        Ok(n)
+    }
+
+    #[cfg(feature = "tokio")]
+    pub(crate) fn drained(&mut self, res: io::Result<usize>) -> io::Result<usize> {
+        self.inner_io_calls += 1;
+        self.inner(res)
+    }
+
+    fn accepted(&mut self, n: usize) {
+        self.bytes += n;
+        self.max_transfer = cmp::max(self.max_transfer, n);
+    }
+}
@@ -0,0 +1,117 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! This module contains the bookkeeping behind `verify_monotonic`.
+
+use std::collections::VecDeque;
+
+/// Checks that the bytes forwarded to an inner writer are exactly the bytes
+/// accepted from the caller, in order.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Monotonic {
+    // The number of bytes reported to the caller as written.
+    accepted: u64,
+    // The stream offset of the next byte the inner writer should receive.
+    forwarded: u64,
+    // Bytes accepted but not yet forwarded, if contents are being checked.
+    unforwarded: Option<VecDeque<u8>>,
+}
+
+impl Monotonic {
+    /// Creates a check that also compares the contents of forwarded bytes
+    /// against what was accepted.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn with_contents() -> Self {
+        Monotonic {
+            unforwarded: Some(VecDeque::new()),
+            ..Monotonic::default()
+        }
+    }
+
+    /// Returns the stream offset of the next byte the caller will write.
+    pub(crate) fn accepted_len(&self) -> u64 {
+        self.accepted
+    }
+
+    /// Records that `n` bytes were reported to the caller as written.
+    pub(crate) fn accepted(&mut self, n: usize) {
+        self.accepted += n as u64;
+    }
+
+    /// Records that `data` was buffered and reported to the caller as written.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn buffered(&mut self, data: &[u8]) {
+        self.accepted(data.len());
+        if let Some(unforwarded) = self.unforwarded.as_mut() {
+            unforwarded.extend(data);
+        }
+    }
+
+    /// Records that the inner writer accepted `data`, starting at stream
+    /// offset `offset`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` isn't right after the previously forwarded bytes, or
+    /// if `data` doesn't match the bytes accepted at that offset.
+    pub(crate) fn forwarded(&mut self, offset: u64, data: &[u8]) {
+        if offset != self.forwarded {
+            panic!("partial-io: non-contiguous write forwarded at offset {}, expected offset {}",
+                   offset,
+                   self.forwarded);
+        }
+        if let Some(unforwarded) = self.unforwarded.as_mut() {
+            if data.len() > unforwarded.len() {
+                panic!("partial-io: {} bytes forwarded at offset {}, but only {} were written",
+                       data.len(),
+                       offset,
+                       unforwarded.len());
+            }
+            let expected = unforwarded.drain(..data.len());
+            if let Some(pos) = expected.zip(data).position(|(a, &b)| a != b) {
+                panic!("partial-io: out-of-order byte forwarded at offset {}",
+                       offset + pos as u64);
+            }
+        }
+        self.forwarded += data.len() as u64;
+    }
+
+    /// Checks that every accepted byte has been forwarded.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of bytes reported to the caller differs from the
+    /// number that reached the inner writer.
+    pub(crate) fn check_forwarded(&self) {
+        if self.accepted != self.forwarded {
+            panic!("partial-io: {} bytes reported as written, but {} reached the inner writer",
+                   self.accepted,
+                   self.forwarded);
+        }
+    }
+
+    /// Replaces the bytes expected next with `data`, after an operation that
+    /// intentionally reorders what was accepted.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn reordered(&mut self, data: &[u8]) {
+        if let Some(unforwarded) = self.unforwarded.as_mut() {
+            unforwarded.clear();
+            unforwarded.extend(data);
+        }
+    }
+
+    /// Accepts the current positions as correct, after an operation that
+    /// intentionally misreports what was written.
+    pub(crate) fn resync(&mut self) {
+        self.accepted = self.forwarded;
+        if let Some(unforwarded) = self.unforwarded.as_mut() {
+            unforwarded.clear();
+        }
+    }
+}
@@ -0,0 +1,181 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! This module contains an extension trait with combinators for iterators over
+//! `PartialOp`s.
+
+use std::cmp;
+use std::io;
+use std::iter::{Peekable, Take};
+
+use PartialOp;
+
+/// Combinators for building sequences of `PartialOp`s out of other sequences.
+///
+/// This is implemented for every iterator over `PartialOp`s, so sequences from
+/// generators like [`degrading`], [`ops_from_pattern`] or `iter::repeat` can be
+/// adjusted by chaining calls.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io;
+/// use std::iter;
+///
+/// use partial_io::{PartialOp, PartialOpIterExt};
+///
+/// let ops: Vec<_> = iter::repeat(PartialOp::Limited(8))
+///     .capped_at(4)
+///     .with_errors_every(2, io::ErrorKind::Interrupted)
+///     .finite(4)
+///     .collect();
+/// assert_eq!(format!("{:?}", ops),
+///            "[Limited(4), Limited(4), Err(Interrupted), Limited(4)]");
+/// ```
+///
+/// [`degrading`]: fn.degrading.html
+/// [`ops_from_pattern`]: fn.ops_from_pattern.html
+pub trait PartialOpIterExt: Iterator<Item = PartialOp> + Sized {
+    /// Inserts an `Err(kind)` after every `n` operations.
+    ///
+    /// The operations from this iterator are all kept, in order. No error is
+    /// added after the last operation, so a finite sequence stays finite.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io;
+    ///
+    /// use partial_io::{PartialOp, PartialOpIterExt};
+    ///
+    /// let ops = vec![PartialOp::Limited(1), PartialOp::Limited(2)]
+    ///     .into_iter()
+    ///     .with_errors_every(1, io::ErrorKind::Interrupted);
+    /// assert_eq!(ops.size_hint(), (3, Some(3)));
+    /// assert_eq!(format!("{:?}", ops.collect::<Vec<_>>()),
+    ///            "[Limited(1), Err(Interrupted), Limited(2)]");
+    /// ```
+    fn with_errors_every(self, n: usize, kind: io::ErrorKind) -> ErrorsEvery<Self> {
+        assert!(n > 0, "with_errors_every requires n > 0");
+        ErrorsEvery {
+            iter: self.peekable(),
+            n,
+            kind,
+            count: 0,
+        }
+    }
+
+    /// Caps every operation at `max` bytes.
+    ///
+    /// `Limited`, `ReadLimited` and `WriteLimited` operations with a larger
+    /// limit are lowered to `max`, and `Unlimited` becomes `Limited(max)`.
+    /// Other operations are left alone.
+    fn capped_at(self, max: usize) -> CappedAt<Self> {
+        CappedAt { iter: self, max }
+    }
+
+    /// Stops after `len` operations.
+    ///
+    /// This is the same as `Iterator::take`. It is useful for making an endless
+    /// sequence like `iter::repeat` finite, so that the wrapper passes calls
+    /// through once the operations run out.
+    fn finite(self, len: usize) -> Take<Self> {
+        self.take(len)
+    }
+}
+
+impl<I> PartialOpIterExt for I where I: Iterator<Item = PartialOp> {}
+
+/// The iterator returned by
+/// [`PartialOpIterExt::with_errors_every`](trait.PartialOpIterExt.html#method.with_errors_every).
+#[derive(Clone, Debug)]
+pub struct ErrorsEvery<I>
+    where I: Iterator<Item = PartialOp>
+{
+    iter: Peekable<I>,
+    n: usize,
+    kind: io::ErrorKind,
+    // The number of operations passed through since the last error.
+    count: usize,
+}
+
+impl<I> ErrorsEvery<I>
+    where I: Iterator<Item = PartialOp>
+{
+    /// Returns how many errors are added among the next `len` operations, or
+    /// `None` if that overflows.
+    fn errors_for(&self, len: usize) -> Option<usize> {
+        if len == 0 {
+            return Some(0);
+        }
+        (len - 1).checked_add(self.count).map(|total| total / self.n)
+    }
+}
+
+impl<I> Iterator for ErrorsEvery<I>
+    where I: Iterator<Item = PartialOp>
+{
+    type Item = PartialOp;
+
+    fn next(&mut self) -> Option<PartialOp> {
+        if self.count == self.n {
+            // Only add an error if an operation follows it.
+            self.iter.peek()?;
+            self.count = 0;
+            return Some(PartialOp::Err(self.kind));
+        }
+        let op = self.iter.next();
+        if op.is_some() {
+            self.count += 1;
+        }
+        op
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lo, hi) = self.iter.size_hint();
+        let lo = lo.saturating_add(self.errors_for(lo).unwrap_or(usize::MAX));
+        let hi = hi.and_then(|hi| self.errors_for(hi).and_then(|errs| hi.checked_add(errs)));
+        (lo, hi)
+    }
+}
+
+/// The iterator returned by
+/// [`PartialOpIterExt::capped_at`](trait.PartialOpIterExt.html#method.capped_at).
+#[derive(Clone, Debug)]
+pub struct CappedAt<I> {
+    iter: I,
+    max: usize,
+}
+
+impl<I> Iterator for CappedAt<I>
+    where I: Iterator<Item = PartialOp>
+{
+    type Item = PartialOp;
+
+    fn next(&mut self) -> Option<PartialOp> {
+        let max = self.max;
+        self.iter
+            .next()
+            .map(|op| match op {
+                     PartialOp::Limited(n) => PartialOp::Limited(cmp::min(n, max)),
+                     PartialOp::ReadLimited(n) => PartialOp::ReadLimited(cmp::min(n, max)),
+                     PartialOp::WriteLimited(n) => PartialOp::WriteLimited(cmp::min(n, max)),
+                     PartialOp::Unlimited => PartialOp::Limited(max),
+                     op => op,
+                 })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
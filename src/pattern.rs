@@ -0,0 +1,149 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! This module contains a terse pattern language for writing out sequences of
+//! `PartialOp`s by hand.
+
+use std::env;
+use std::error;
+use std::fmt;
+use std::io;
+
+use PartialOp;
+
+/// Expands a pattern string into a sequence of `PartialOp`s.
+///
+/// This is meant for quickly sketching out interleavings in tests. Each
+/// character of the pattern stands for one operation:
+///
+/// * `L` is `PartialOp::Limited(1)`. It may be followed by a decimal number,
+///   so `L3` is `PartialOp::Limited(3)`.
+/// * `U` is `PartialOp::Unlimited`.
+/// * `W` is `PartialOp::Err(io::ErrorKind::WouldBlock)`.
+/// * `I` is `PartialOp::Err(io::ErrorKind::Interrupted)`.
+///
+/// Whitespace is ignored, so long patterns can be broken up for readability.
+/// Any other character results in an error.
+///
+/// # Examples
+///
+/// ```rust
+/// use partial_io::ops_from_pattern;
+///
+/// let ops = ops_from_pattern("L L3 W U").unwrap();
+/// assert_eq!(format!("{:?}", ops), "[Limited(1), Limited(3), Err(WouldBlock), Unlimited]");
+///
+/// assert!(ops_from_pattern("LX").is_err());
+/// ```
+pub fn ops_from_pattern(pattern: &str) -> Result<Vec<PartialOp>, PatternError> {
+    let mut ops = Vec::new();
+    let mut chars = pattern.char_indices().peekable();
+
+    while let Some((index, ch)) = chars.next() {
+        let op = match ch {
+            'L' => {
+                let mut limit: Option<usize> = None;
+                while let Some(&(digit_index, digit)) = chars.peek() {
+                    let value = match digit.to_digit(10) {
+                        Some(value) => value as usize,
+                        None => break,
+                    };
+                    limit = limit
+                        .unwrap_or(0)
+                        .checked_mul(10)
+                        .and_then(|limit| limit.checked_add(value));
+                    if limit.is_none() {
+                        return Err(PatternError::new(digit_index, PatternErrorKind::Overflow));
+                    }
+                    chars.next();
+                }
+                PartialOp::Limited(limit.unwrap_or(1))
+            }
+            'U' => PartialOp::Unlimited,
+            'W' => PartialOp::Err(io::ErrorKind::WouldBlock),
+            'I' => PartialOp::Err(io::ErrorKind::Interrupted),
+            ch if ch.is_whitespace() => continue,
+            ch => return Err(PatternError::new(index, PatternErrorKind::UnknownChar(ch))),
+        };
+        ops.push(op);
+    }
+
+    Ok(ops)
+}
+
+/// The environment variable read by `from_env_or` constructors.
+pub const DEFAULT_OPS_ENV: &str = "PARTIAL_IO_DEFAULT_OPS";
+
+/// Parses the operations in the `PARTIAL_IO_DEFAULT_OPS` environment variable,
+/// if it is set.
+///
+/// # Panics
+///
+/// Panics if the variable is not valid Unicode or not a valid pattern, since a
+/// broken fault profile should fail the test rather than be silently ignored.
+pub(crate) fn ops_from_env() -> Option<Vec<PartialOp>> {
+    let pattern = match env::var(DEFAULT_OPS_ENV) {
+        Ok(pattern) => pattern,
+        Err(env::VarError::NotPresent) => return None,
+        Err(err) => panic!("invalid {}: {}", DEFAULT_OPS_ENV, err),
+    };
+    match ops_from_pattern(&pattern) {
+        Ok(ops) => Some(ops),
+        Err(err) => panic!("invalid {}: {}", DEFAULT_OPS_ENV, err),
+    }
+}
+
+/// The error returned by [`ops_from_pattern`] if a pattern is malformed.
+///
+/// [`ops_from_pattern`]: fn.ops_from_pattern.html
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PatternError {
+    index: usize,
+    kind: PatternErrorKind,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum PatternErrorKind {
+    UnknownChar(char),
+    Overflow,
+}
+
+impl PatternError {
+    fn new(index: usize, kind: PatternErrorKind) -> Self {
+        PatternError {
+            index,
+            kind,
+        }
+    }
+
+    /// Returns the byte index in the pattern at which the error occurred.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            PatternErrorKind::UnknownChar(ch) => {
+                write!(f,
+                       "unknown character {:?} at index {} in partial-io op pattern",
+                       ch,
+                       self.index)
+            }
+            PatternErrorKind::Overflow => {
+                write!(f,
+                       "limit overflows usize at index {} in partial-io op pattern",
+                       self.index)
+            }
+        }
+    }
+}
+
+impl error::Error for PatternError {}
@@ -49,6 +49,8 @@
 //! [`PartialAsyncWrite`]: ../struct.PartialAsyncWrite.html
 //! [`GenError`]: trait.GenError.html
 
+use std::error;
+use std::fmt;
 use std::io;
 use std::marker::PhantomData;
 use std::ops::Deref;
@@ -150,6 +152,225 @@ impl GenError for GenNoErrors {
     }
 }
 
+/// A builder for error generators that pick error kinds according to
+/// per-kind weights.
+///
+/// The built-in `GenError` implementations pick error kinds uniformly. To model
+/// more realistic conditions, say `WouldBlock` errors being common and
+/// `BrokenPipe` errors rare, set a weight for each kind and the overall
+/// probability of generating an error. Weights are relative to each other, and
+/// are normalized when the generator is built.
+///
+/// To use the built generator with `PartialWithErrors`, implement
+/// [`ErrorWeights`] for a type of your own and use [`GenWeighted`].
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io;
+///
+/// use partial_io::GenErrorBuilder;
+///
+/// let builder = GenErrorBuilder::new()
+///     .probability(0.3)
+///     .weight(io::ErrorKind::WouldBlock, 10.0)
+///     .weight(io::ErrorKind::BrokenPipe, 1.0);
+/// assert!(builder.build().is_ok());
+///
+/// // A set of weights that are all zero is rejected.
+/// let builder = GenErrorBuilder::new().weight(io::ErrorKind::WouldBlock, 0.0);
+/// assert!(builder.build().is_err());
+/// ```
+///
+/// [`ErrorWeights`]: trait.ErrorWeights.html
+/// [`GenWeighted`]: struct.GenWeighted.html
+#[derive(Clone, Debug)]
+pub struct GenErrorBuilder {
+    probability: f64,
+    weights: Vec<(io::ErrorKind, f64)>,
+}
+
+impl GenErrorBuilder {
+    /// Creates a new builder with no weights set.
+    ///
+    /// The probability of generating an error defaults to 20%, matching the
+    /// built-in generators.
+    pub fn new() -> Self {
+        GenErrorBuilder {
+            probability: 0.2,
+            weights: Vec::new(),
+        }
+    }
+
+    /// Sets the probability, between 0 and 1, of generating an error.
+    pub fn probability(mut self, probability: f64) -> Self {
+        self.probability = probability;
+        self
+    }
+
+    /// Sets the relative weight of an error kind, replacing any weight set for
+    /// it previously.
+    pub fn weight(mut self, kind: io::ErrorKind, weight: f64) -> Self {
+        self.weights.retain(|&(k, _)| k != kind);
+        self.weights.push((kind, weight));
+        self
+    }
+
+    /// Builds the error generator.
+    ///
+    /// This fails if the probability is not between 0 and 1, if any weight is
+    /// negative or not finite, or if all weights are zero.
+    pub fn build(self) -> Result<WeightedErrors, WeightsError> {
+        if !(self.probability >= 0.0 && self.probability <= 1.0) {
+            return Err(WeightsError::InvalidProbability);
+        }
+        if self.weights.iter().any(|&(_, w)| !(w >= 0.0 && w.is_finite())) {
+            return Err(WeightsError::InvalidWeight);
+        }
+        let total: f64 = self.weights.iter().map(|&(_, w)| w).sum();
+        if total == 0.0 {
+            return Err(WeightsError::AllZero);
+        }
+        let weights = self.weights
+            .into_iter()
+            .filter(|&(_, w)| w > 0.0)
+            .map(|(k, w)| (k, w / total))
+            .collect();
+        Ok(WeightedErrors {
+            probability: self.probability,
+            weights,
+        })
+    }
+}
+
+impl Default for GenErrorBuilder {
+    fn default() -> Self {
+        GenErrorBuilder::new()
+    }
+}
+
+/// An error generator built by [`GenErrorBuilder`].
+///
+/// [`GenErrorBuilder`]: struct.GenErrorBuilder.html
+#[derive(Clone, Debug)]
+pub struct WeightedErrors {
+    probability: f64,
+    // Normalized so that the weights add up to 1.
+    weights: Vec<(io::ErrorKind, f64)>,
+}
+
+impl WeightedErrors {
+    /// Optionally generate an `io::ErrorKind` instance.
+    pub fn gen_error<G: Gen>(&mut self, g: &mut G) -> Option<io::ErrorKind> {
+        if g.gen::<f64>() >= self.probability {
+            return None;
+        }
+        let mut choice = g.gen::<f64>();
+        for &(kind, weight) in &self.weights {
+            if choice < weight {
+                return Some(kind);
+            }
+            choice -= weight;
+        }
+        // Floating-point rounding can leave a tiny bit of choice over.
+        self.weights.last().map(|&(kind, _)| kind)
+    }
+}
+
+/// The error returned when a [`GenErrorBuilder`] is invalid.
+///
+/// [`GenErrorBuilder`]: struct.GenErrorBuilder.html
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum WeightsError {
+    /// The probability of generating an error was not between 0 and 1.
+    InvalidProbability,
+    /// A weight was negative or not finite.
+    InvalidWeight,
+    /// No weights were set, or all of them were zero.
+    AllZero,
+}
+
+impl fmt::Display for WeightsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match *self {
+            WeightsError::InvalidProbability => "error probability must be between 0 and 1",
+            WeightsError::InvalidWeight => "error weights must be finite and non-negative",
+            WeightsError::AllZero => "at least one error weight must be non-zero",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl error::Error for WeightsError {}
+
+/// Provides the weights for a [`GenWeighted`] error generator.
+///
+/// See [`GenWeighted`] for an example.
+///
+/// [`GenWeighted`]: struct.GenWeighted.html
+pub trait ErrorWeights: Clone + Send {
+    /// Returns a builder with the weights to use.
+    fn weights() -> GenErrorBuilder;
+}
+
+/// Generate errors according to the weights provided by an [`ErrorWeights`]
+/// implementation.
+///
+/// # Panics
+///
+/// Creating this generator panics if the weights are invalid.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// use std::io;
+///
+/// use partial_io::{ErrorWeights, GenErrorBuilder, GenWeighted, PartialWithErrors};
+///
+/// #[derive(Clone)]
+/// struct MostlyWouldBlock;
+///
+/// impl ErrorWeights for MostlyWouldBlock {
+///     fn weights() -> GenErrorBuilder {
+///         GenErrorBuilder::new()
+///             .weight(io::ErrorKind::WouldBlock, 10.0)
+///             .weight(io::ErrorKind::BrokenPipe, 1.0)
+///     }
+/// }
+///
+/// quickcheck! {
+///     fn test_something(seq: PartialWithErrors<GenWeighted<MostlyWouldBlock>>) {
+///         // ...
+///     }
+/// }
+/// ```
+///
+/// [`ErrorWeights`]: trait.ErrorWeights.html
+#[derive(Clone, Debug)]
+pub struct GenWeighted<EW> {
+    errors: WeightedErrors,
+    _marker: PhantomData<EW>,
+}
+
+impl<EW> GenError for GenWeighted<EW>
+    where EW: ErrorWeights
+{
+    fn new() -> Self {
+        let errors = match EW::weights().build() {
+            Ok(errors) => errors,
+            Err(err) => panic!("invalid error weights: {}", err),
+        };
+        GenWeighted {
+            errors,
+            _marker: PhantomData,
+        }
+    }
+
+    fn gen_error<G: Gen>(&mut self, g: &mut G) -> Option<io::ErrorKind> {
+        self.errors.gen_error(g)
+    }
+}
+
 impl<GE> Arbitrary for PartialWithErrors<GE>
     where GE: GenError + 'static
 {
@@ -169,18 +390,18 @@ impl<GE> Arbitrary for PartialWithErrors<GE>
                  })
             .collect();
         PartialWithErrors {
-            items: items,
+            items,
             _marker: PhantomData,
         }
     }
 
-    fn shrink(&self) -> Box<Iterator<Item = Self>> {
+    fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
         Box::new(self.items
                      .clone()
                      .shrink()
                      .map(|items| {
                               PartialWithErrors {
-                                  items: items,
+                                  items,
                                   _marker: PhantomData,
                               }
                           }))
@@ -193,14 +414,14 @@ impl Arbitrary for PartialOp {
         unimplemented!();
     }
 
-    fn shrink(&self) -> Box<Iterator<Item = Self>> {
+    fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
         match self {
             // Skip 0 because for writers it can mean that writes are no longer
             // accepted.
             &PartialOp::Limited(n) => {
                 Box::new(n.shrink()
                              .filter(|k| k != &0)
-                             .map(|k| PartialOp::Limited(k)))
+                             .map(PartialOp::Limited))
             }
             _ => empty_shrinker(),
         }
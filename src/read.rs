@@ -11,10 +11,14 @@
 //! a provided iterator.
 
 use std::cmp;
-use std::io::{self, Read};
+use std::collections::VecDeque;
+use std::io::{self, Cursor, Read};
 use std::iter::Fuse;
+use std::panic::UnwindSafe;
 
-use PartialOp;
+use {ErrorCallback, Metrics, OpKind, PartialIoError, PartialOp, aligned_len, trace_op};
+use segments::Segments;
+use utf8::utf8_split_ops;
 
 /// A reader wrapper that breaks inner `Read` instances up according to the
 /// provided iterator.
@@ -40,6 +44,43 @@ pub struct PartialRead<R, I>
 {
     inner: R,
     iter: Fuse<I::IntoIter>,
+    metrics: Metrics,
+    max_ops: Option<usize>,
+    on_injected_error: Option<ErrorCallback>,
+    // The bytes returned by the last read, for PartialOp::Duplicate.
+    last_read: Vec<u8>,
+    offset_faults: Option<OffsetFaults>,
+    when_buf: Option<BufPredicate>,
+    // The number of bytes left in the inner reader, for PartialOp::ErrOnLast.
+    len_left: Option<u64>,
+}
+
+/// The function for `PartialRead::when_buf`.
+type BufPredicate = Box<dyn FnMut(usize) -> Option<PartialOp> + Send + Sync + UnwindSafe>;
+
+/// The state for `PartialRead::fail_at_offsets`.
+struct OffsetFaults {
+    inner_len: u64,
+    // In ascending order, with the offsets that have already failed removed.
+    offsets: VecDeque<u64>,
+    kind: io::ErrorKind,
+    // The number of bytes read from the inner reader so far.
+    pos: u64,
+}
+
+impl OffsetFaults {
+    /// Returns the error kind to fail a read of up to `len` bytes with, if it
+    /// would deliver the byte at the next offset.
+    fn check(&mut self, len: usize) -> Option<io::ErrorKind> {
+        let deliver = cmp::min(len as u64, self.inner_len.saturating_sub(self.pos));
+        match self.offsets.front() {
+            Some(&offset) if offset < self.pos + deliver => {
+                self.offsets.pop_front();
+                Some(self.kind)
+            }
+            _ => None,
+        }
+    }
 }
 
 impl<R, I> PartialRead<R, I>
@@ -48,11 +89,126 @@ impl<R, I> PartialRead<R, I>
 {
     pub fn new(inner: R, iter: I) -> Self {
         PartialRead {
-            inner: inner,
+            inner,
             iter: iter.into_iter().fuse(),
+            metrics: Metrics::default(),
+            max_ops: None,
+            on_injected_error: None,
+            last_read: Vec::new(),
+            offset_faults: None,
+            when_buf: None,
+            len_left: None,
         }
     }
 
+    /// Creates a new wrapper around an inner reader of known length.
+    ///
+    /// `inner_len` is the total number of bytes `inner` returns before the end
+    /// of the stream. It is used by [`PartialOp::ErrOnLast`] to tell which read
+    /// is the last one. Otherwise this is the same as [`new`].
+    ///
+    /// [`PartialOp::ErrOnLast`]: enum.PartialOp.html#variant.ErrOnLast
+    /// [`new`]: #method.new
+    pub fn with_len(inner: R, inner_len: u64, iter: I) -> Self {
+        let mut reader = PartialRead::new(inner, iter);
+        reader.len_left = Some(inner_len);
+        reader
+    }
+
+    /// Calls `callback` every time this wrapper returns an error that it
+    /// injected.
+    ///
+    /// This is for checking that the code under test's error handling
+    /// actually ran, rather than errors being swallowed. Count the calls, and
+    /// compare the count against what the code under test reported, for
+    /// example how many errors it logged or returned. Every injected error is
+    /// passed, including ones that the code under test is right to recover
+    /// from by retrying, like `Interrupted` or `WouldBlock`, so filter on
+    /// `err.kind()` to only count the ones that should reach the end.
+    ///
+    /// Errors from the underlying reader are not passed. Calling this again
+    /// replaces the previous callback. The callback must be `Sync` and
+    /// `UnwindSafe`, like the wrapper itself, which closures over atomics or
+    /// mutexes are.
+    pub fn on_injected_error<F>(&mut self, callback: F)
+        where F: FnMut(&io::Error) + Send + Sync + UnwindSafe + 'static
+    {
+        self.on_injected_error = Some(Box::new(callback));
+    }
+
+    /// Makes every call fail once more than `max_ops` calls have been made.
+    ///
+    /// This is a guardrail against runaway tests: if the code under test gets
+    /// stuck in a loop, it fails fast with an `Other` error saying that the
+    /// partial-io operation limit was exceeded, instead of spinning forever.
+    /// Every `read` call counts towards the limit, whatever operation it ends
+    /// up with. Once the limit is exceeded, calls no longer reach the
+    /// underlying reader or take operations from the iterator.
+    ///
+    /// There is no limit by default.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::{self, Cursor, Read};
+    ///
+    /// use partial_io::{PartialRead, always_interrupted};
+    ///
+    /// let mut partial_reader = PartialRead::new(Cursor::new(vec![1, 2, 3]), always_interrupted());
+    /// partial_reader.set_max_ops(100);
+    ///
+    /// // read_to_end would retry the interruptions forever, but the limit
+    /// // makes it fail instead.
+    /// let err = partial_reader.read_to_end(&mut Vec::new()).unwrap_err();
+    /// assert_eq!(err.kind(), io::ErrorKind::Other);
+    /// assert_eq!(partial_reader.metrics().calls, 101);
+    /// assert_eq!(partial_reader.last_op_was_injected(), Some(true));
+    /// ```
+    pub fn set_max_ops(&mut self, max_ops: usize) {
+        self.max_ops = Some(max_ops);
+    }
+
+    /// Returns a snapshot of the counters kept by this wrapper.
+    ///
+    /// See [`Metrics`](struct.Metrics.html) for more.
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.clone()
+    }
+
+    /// Returns the number of times this reader returned `Ok(0)` for a
+    /// non-empty buffer, signaling the end of the stream.
+    ///
+    /// Code that reads until the end of the stream should see this exactly once.
+    /// More than that means it kept reading after the end, and 0 means it never
+    /// got there.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::{Cursor, Read};
+    ///
+    /// use partial_io::{PartialOp, PartialRead};
+    ///
+    /// let ops = ::std::iter::repeat(PartialOp::Limited(2));
+    /// let mut partial_reader = PartialRead::new(Cursor::new(vec![1, 2, 3]), ops);
+    /// let mut out = Vec::new();
+    /// partial_reader.read_to_end(&mut out).unwrap();
+    /// assert_eq!(partial_reader.eof_seen_count(), 1);
+    /// ```
+    pub fn eof_seen_count(&self) -> usize {
+        self.metrics.eofs
+    }
+
+    /// Returns whether the most recent call applied an operation from the
+    /// iterator, or passed straight through to the underlying reader.
+    ///
+    /// Returns `None` if no calls have been made yet. See
+    /// [`Metrics::last_op_injected`](struct.Metrics.html#structfield.last_op_injected)
+    /// for more.
+    pub fn last_op_was_injected(&self) -> Option<bool> {
+        self.metrics.last_op_injected
+    }
+
     /// Acquires a reference to the underlying reader.
     pub fn get_ref(&self) -> &R {
         &self.inner
@@ -67,6 +223,180 @@ impl<R, I> PartialRead<R, I>
     pub fn into_inner(self) -> R {
         self.inner
     }
+
+    /// Returns whether a read into a buffer of length `buf_len` would return
+    /// the last bytes of the inner reader, if its length is known.
+    fn is_last(&self, buf_len: usize) -> bool {
+        match self.len_left {
+            Some(left) => left <= buf_len as u64,
+            None => false,
+        }
+    }
+
+    /// Passes `res` to the `on_injected_error` callback if it is an injected
+    /// error.
+    fn returned<T>(&mut self, res: io::Result<T>) -> io::Result<T> {
+        if let (Err(err), Some(callback)) = (&res, self.on_injected_error.as_mut()) {
+            if let Some(true) = PartialIoError::from_io(err).map(PartialIoError::is_injected) {
+                callback(err);
+            }
+        }
+        res
+    }
+}
+
+impl PartialRead<Cursor<Vec<u8>>, Vec<PartialOp>> {
+    /// Creates a new wrapper that reads `data`, cutting every multi-byte
+    /// character in half across two reads.
+    ///
+    /// This is meant for testing incremental UTF-8 decoders, including
+    /// `read_to_string`. See [`utf8_split_ops`] for more.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::Read;
+    ///
+    /// use partial_io::PartialRead;
+    ///
+    /// let mut partial_reader = PartialRead::utf8_split("Grüße, ☃!".to_string());
+    /// let mut out = String::new();
+    /// partial_reader.read_to_string(&mut out).unwrap();
+    /// assert_eq!(out, "Grüße, ☃!");
+    /// ```
+    ///
+    /// [`utf8_split_ops`]: fn.utf8_split_ops.html
+    pub fn utf8_split(data: String) -> Self {
+        let ops = utf8_split_ops(&data);
+        PartialRead::new(Cursor::new(data.into_bytes()), ops)
+    }
+}
+
+impl<I> PartialRead<Segments, I>
+    where I: IntoIterator<Item = PartialOp>
+{
+    /// Creates a new wrapper that reads `segments` in order, without ever
+    /// returning data from two segments in a single read.
+    ///
+    /// Segment boundaries act as additional limits on top of the operations:
+    /// a read ends at the end of the current segment even if the operation
+    /// would have allowed it to go further. This models reading from distinct
+    /// network packets, and is useful for testing parsers of concatenated
+    /// streams. Empty segments are skipped, and once every segment has been
+    /// read, reads signal the end of the stream. See [`Segments`] for more.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::Read;
+    ///
+    /// use partial_io::{PartialOp, PartialRead};
+    ///
+    /// let segments = vec![b"Hello".to_vec(), b", world!".to_vec()];
+    /// let mut partial_reader = PartialRead::segments(segments, vec![PartialOp::Limited(3)]);
+    /// let mut buf = [0; 16];
+    ///
+    /// assert_eq!(partial_reader.read(&mut buf).unwrap(), 3);
+    /// // The rest of the first segment, even though there's room for more.
+    /// assert_eq!(partial_reader.read(&mut buf).unwrap(), 2);
+    /// assert_eq!(partial_reader.read(&mut buf).unwrap(), 8);
+    /// assert_eq!(partial_reader.read(&mut buf).unwrap(), 0);
+    /// ```
+    ///
+    /// [`Segments`]: struct.Segments.html
+    pub fn segments(segments: Vec<Vec<u8>>, iter: I) -> Self {
+        PartialRead::new(Segments::new(segments), iter)
+    }
+}
+
+impl<R> PartialRead<R, Vec<PartialOp>>
+    where R: Read
+{
+    /// Creates a new wrapper that fails reads that would deliver the bytes at
+    /// particular offsets in the stream.
+    ///
+    /// `inner_len` is the total length of `inner`, which is used to tell how
+    /// many bytes a read would deliver near the end of the stream. A read that
+    /// would deliver the byte at any of `offsets` fails with an error of the
+    /// given kind instead, without reaching `inner`. Each offset only fails
+    /// once, so retrying the read goes past it. Offsets past `inner_len` never
+    /// fail. This is useful for reproducing a fault at a known position, like
+    /// corruption found in a real capture, independently of how the code under
+    /// test sizes its reads.
+    ///
+    /// If a read would span several offsets, it only fails for the first one.
+    /// Retrying it then fails for the next one, and so on, so every offset
+    /// results in exactly one error.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::{self, Cursor, Read};
+    ///
+    /// use partial_io::PartialRead;
+    ///
+    /// let data = vec![0; 100];
+    /// let mut partial_reader =
+    ///     PartialRead::fail_at_offsets(Cursor::new(data), 100, vec![50], io::ErrorKind::Other);
+    /// let mut buf = [0; 40];
+    ///
+    /// assert_eq!(partial_reader.read(&mut buf).unwrap(), 40);
+    /// // The next 40 bytes would include offset 50.
+    /// assert_eq!(partial_reader.read(&mut buf).unwrap_err().kind(), io::ErrorKind::Other);
+    /// assert_eq!(partial_reader.read(&mut buf).unwrap(), 40);
+    /// ```
+    pub fn fail_at_offsets(inner: R,
+                           inner_len: u64,
+                           mut offsets: Vec<u64>,
+                           kind: io::ErrorKind)
+                           -> Self {
+        offsets.sort();
+        let mut reader = PartialRead::new(inner, Vec::new());
+        reader.offset_faults = Some(OffsetFaults {
+                                        inner_len,
+                                        offsets: offsets.into_iter().collect(),
+                                        kind,
+                                        pos: 0,
+                                    });
+        reader
+    }
+
+    /// Creates a new wrapper that picks the operation for each read based on
+    /// the size of the buffer passed in.
+    ///
+    /// `f` is called once per read with the length of the caller's buffer, and
+    /// returns the operation to apply, or `None` to pass the read through.
+    /// This can target reads by size, which positional operations can't do:
+    /// for example, failing only the large reads of code that also makes small
+    /// probing reads. `f` sees the length as requested, before any limiting.
+    /// Like the wrapper itself, `f` must be `Sync` and `UnwindSafe`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::{self, Cursor, Read};
+    ///
+    /// use partial_io::{PartialOp, PartialRead};
+    ///
+    /// let mut partial_reader = PartialRead::when_buf(Cursor::new(vec![0; 100]), |len| {
+    ///     if len > 16 {
+    ///         Some(PartialOp::Err(io::ErrorKind::Other))
+    ///     } else {
+    ///         None
+    ///     }
+    /// });
+    ///
+    /// assert_eq!(partial_reader.read(&mut [0; 1]).unwrap(), 1);
+    /// let err = partial_reader.read(&mut [0; 64]).unwrap_err();
+    /// assert_eq!(err.kind(), io::ErrorKind::Other);
+    /// ```
+    pub fn when_buf<F>(inner: R, f: F) -> Self
+        where F: FnMut(usize) -> Option<PartialOp> + Send + Sync + UnwindSafe + 'static
+    {
+        let mut reader = PartialRead::new(inner, Vec::new());
+        reader.when_buf = Some(Box::new(f));
+        reader
+    }
 }
 
 impl<R, I> Read for PartialRead<R, I>
@@ -74,16 +404,101 @@ impl<R, I> Read for PartialRead<R, I>
           I: IntoIterator<Item = PartialOp>
 {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        match self.iter.next() {
+        self.metrics.call(buf.len());
+        if let Some(err) = self.metrics.limit_exceeded(self.max_ops, OpKind::Read) {
+            let res = Err(err);
+            trace_op(OpKind::Read, &self.metrics, &None, Some(buf.len()), &res);
+            return self.returned(res);
+        }
+        let offset_fault = match self.offset_faults {
+            Some(ref mut faults) => faults.check(buf.len()),
+            None => None,
+        };
+        if let Some(kind) = offset_fault {
+            self.last_read.clear();
+            let res = Err(self.metrics.injected_by_wrapper(kind,
+                                                           OpKind::Read,
+                                                           "error at offset during read, \
+                                                            generated by partial-io"));
+            trace_op(OpKind::Read, &self.metrics, &None, Some(buf.len()), &res);
+            return self.returned(res);
+        }
+        let op = match self.when_buf {
+            Some(ref mut f) => f(buf.len()),
+            None => self.iter.next(),
+        };
+        let op = self.metrics.op(op.map(PartialOp::for_read));
+        let duplicate = match op {
+            Some(PartialOp::Duplicate) => !self.last_read.is_empty(),
+            _ => false,
+        };
+        let res = match op {
             Some(PartialOp::Limited(n)) => {
                 let len = cmp::min(n, buf.len());
-                self.inner.read(&mut buf[..len])
+                self.metrics.transferred(self.inner.read(&mut buf[..len]))
+            }
+            Some(PartialOp::AlignedTo(align)) => {
+                let len = aligned_len(buf.len(), align);
+                if len == 0 && !buf.is_empty() {
+                    Err(self.metrics.injected(io::ErrorKind::InvalidInput,
+                                              OpKind::Read,
+                                              "buffer smaller than alignment during read, \
+                                               generated by partial-io"))
+                } else {
+                    self.metrics.transferred(self.inner.read(&mut buf[..len]))
+                }
             }
+            // The count is replaced below, once the bytes have been remembered.
+            Some(PartialOp::ReportExactly(_)) => self.metrics.transferred(self.inner.read(buf)),
             Some(PartialOp::Err(err)) => {
-                Err(io::Error::new(err, "error during read, generated by partial-io"))
+                Err(self.metrics.injected(err,
+                                          OpKind::Read,
+                                          "error during read, generated by partial-io"))
+            }
+            Some(PartialOp::ErrOnLast(err)) if self.is_last(buf.len()) => {
+                Err(self.metrics.injected(err,
+                                          OpKind::Read,
+                                          "error on last read, generated by partial-io"))
             }
+            Some(PartialOp::Duplicate) if duplicate => {
+                let len = cmp::min(self.last_read.len(), buf.len());
+                buf[..len].copy_from_slice(&self.last_read[..len]);
+                self.metrics.buffered(len)
+            }
+            // With nothing to duplicate, this is a passthrough.
+            Some(PartialOp::Duplicate) |
+            // This isn't the last read, or the length isn't known.
+            Some(PartialOp::ErrOnLast(_)) |
+            // Direction-specific operations have already been resolved.
+            Some(PartialOp::DropAfter(_)) |
+            Some(PartialOp::ErrAfterFlush(_)) |
+            Some(PartialOp::ReadLimited(_)) |
+            Some(PartialOp::WriteLimited(_)) |
+            Some(PartialOp::ReadErr(_)) |
+            Some(PartialOp::WriteErr(_)) |
+            Some(PartialOp::AsyncWriteZero) |
+            Some(PartialOp::VectoredLimited(_)) |
             Some(PartialOp::Unlimited) |
-            None => self.inner.read(buf),
+            None => self.metrics.transferred(self.inner.read(buf)),
+        };
+        if !duplicate {
+            self.last_read.clear();
+            if let Ok(n) = res {
+                if let Some(ref mut faults) = self.offset_faults {
+                    faults.pos += n as u64;
+                }
+                if let Some(ref mut left) = self.len_left {
+                    *left = left.saturating_sub(n as u64);
+                }
+                self.last_read.extend_from_slice(&buf[..cmp::min(n, buf.len())]);
+            }
         }
+        let res = match op {
+            Some(PartialOp::ReportExactly(n)) => res.map(|_| n),
+            _ => res,
+        };
+        self.metrics.read_returned(buf.len(), &res);
+        trace_op(OpKind::Read, &self.metrics, &op, Some(buf.len()), &res);
+        self.returned(res)
     }
 }
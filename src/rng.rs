@@ -0,0 +1,53 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! This module contains the seeded random number generator used for
+//! reproducible fault injection.
+
+/// A small, deterministic pseudo-random number generator.
+///
+/// This is xorshift64*, seeded through splitmix64 so that any seed, including
+/// 0, works. It is only meant for generating test sequences, but unlike the
+/// generators in `rand`, its output is guaranteed to stay the same across
+/// platforms and releases, so seeds stay reproducible.
+#[derive(Clone, Debug)]
+pub(crate) struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    pub(crate) fn new(seed: u64) -> Self {
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        // xorshift gets stuck at 0, so avoid it.
+        XorShift { state: if z == 0 { 1 } else { z } }
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Returns a number in `[0, n)`. `n` must be non-zero.
+    ///
+    /// This has a slight bias towards smaller numbers, which doesn't matter
+    /// for the sizes used in tests.
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// Returns a number in `[0, 1)`.
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
@@ -0,0 +1,81 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! This module contains a reader over a sequence of separate segments of data.
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::{self, Read};
+
+/// A reader over a sequence of segments, where no read spans two segments.
+///
+/// Each `read` returns data from the current segment only, even if the buffer
+/// has room for more, and moves on to the next segment once the current one
+/// has been read in full. This models reading from a network connection where
+/// each read returns data from a single packet. Empty segments are skipped,
+/// so that they aren't mistaken for the end of the stream. After the last
+/// segment, reads return `Ok(0)`.
+///
+/// This is usually created through [`PartialRead::segments`], which adds
+/// partial reads on top.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::Read;
+///
+/// use partial_io::Segments;
+///
+/// let mut reader = Segments::new(vec![b"abc".to_vec(), vec![], b"de".to_vec()]);
+/// let mut buf = [0; 16];
+/// assert_eq!(reader.read(&mut buf).unwrap(), 3);
+/// assert_eq!(reader.read(&mut buf).unwrap(), 2);
+/// assert_eq!(reader.read(&mut buf).unwrap(), 0);
+/// ```
+///
+/// [`PartialRead::segments`]: struct.PartialRead.html#method.segments
+#[derive(Clone, Debug)]
+pub struct Segments {
+    segments: VecDeque<Vec<u8>>,
+    // The position in the first segment up to which data has been read.
+    pos: usize,
+}
+
+impl Segments {
+    pub fn new(segments: Vec<Vec<u8>>) -> Self {
+        Segments {
+            segments: segments.into_iter().filter(|segment| !segment.is_empty()).collect(),
+            pos: 0,
+        }
+    }
+
+    /// Returns the number of segments that haven't been read in full yet.
+    pub fn remaining_segments(&self) -> usize {
+        self.segments.len()
+    }
+}
+
+impl Read for Segments {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = match self.segments.front() {
+            Some(segment) => {
+                let len = cmp::min(segment.len() - self.pos, buf.len());
+                buf[..len].copy_from_slice(&segment[self.pos..self.pos + len]);
+                len
+            }
+            None => return Ok(0),
+        };
+        self.pos += len;
+        if self.pos == self.segments[0].len() {
+            self.segments.pop_front();
+            self.pos = 0;
+        }
+        Ok(len)
+    }
+}
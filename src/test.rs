@@ -0,0 +1,172 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! Ready-made checks for `Read` and `Write` implementations.
+//!
+//! The functions in this module drive a reader or writer built by the caller
+//! through partial IO, and panic with a descriptive message if it misbehaves.
+//! They are meant to be called from tests.
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::iter::{self, Repeat};
+use std::rc::Rc;
+
+use {PartialOp, PartialWrite};
+
+/// The writer passed to the closure in [`assert_writer_drains`].
+///
+/// [`assert_writer_drains`]: fn.assert_writer_drains.html
+pub type TrickleWrite = PartialWrite<SharedBuffer, Repeat<PartialOp>>;
+
+/// A writer that appends to a buffer shared with the check that created it.
+///
+/// This lets a check look at the bytes that were written even after the writer
+/// has been moved into the adapter under test.
+#[derive(Debug)]
+pub struct SharedBuffer {
+    buf: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Asserts that a writer adapter gets every byte through to the underlying
+/// writer, even if the underlying writer only accepts one byte at a time.
+///
+/// `make_writer` is called with a `PartialWrite` that accepts one byte per
+/// `write` call, and should return the adapter under test wrapped around it.
+/// All of `data` is then written to the adapter, which is then flushed and
+/// dropped. Finally, the bytes that made it through the `PartialWrite` are
+/// compared against `data`.
+///
+/// `Interrupted` errors returned by the adapter are retried.
+///
+/// # Panics
+///
+/// Panics if the adapter returns an error, if it stops accepting data, or if
+/// the bytes that reached the underlying writer don't match `data`.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::BufWriter;
+///
+/// use partial_io::test::assert_writer_drains;
+///
+/// assert_writer_drains(|w| BufWriter::with_capacity(16, w), b"Hello, world!");
+/// ```
+pub fn assert_writer_drains<F, W>(make_writer: F, data: &[u8])
+    where F: FnOnce(TrickleWrite) -> W,
+          W: Write
+{
+    let buf = Rc::new(RefCell::new(Vec::new()));
+    let shared = SharedBuffer { buf: buf.clone() };
+    let mut writer = make_writer(PartialWrite::new(shared, iter::repeat(PartialOp::Limited(1))));
+    if let Err(err) = write_all(&mut writer, data) {
+        panic!("writer failed: {}", err);
+    }
+    drop(writer);
+    assert_same_bytes(&buf.borrow(), data);
+}
+
+/// Asserts that two writers produce the same bytes when driven through the same
+/// sequence of operations.
+///
+/// This is meant for differential testing, for example of an optimized writer
+/// against a simpler reference one. `make_a` and `make_b` are each called once
+/// to create a writer, which is then wrapped in a `PartialWrite` using its own
+/// clone of `ops`. All of `data` is written to both wrappers using the same
+/// loop, and both are flushed. Finally, the bytes held by the two writers are
+/// compared.
+///
+/// `Interrupted` errors are retried. Any other error stops the loop, in which
+/// case both writers must have failed with the same kind of error.
+///
+/// # Panics
+///
+/// Panics if one writer fails and the other doesn't, if they fail with
+/// different kinds of errors, or if the bytes they hold don't match.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io;
+///
+/// use partial_io::PartialOp;
+/// use partial_io::test::assert_writers_equivalent;
+///
+/// let ops = vec![PartialOp::Limited(2),
+///                PartialOp::Err(io::ErrorKind::Interrupted),
+///                PartialOp::Limited(5)];
+/// assert_writers_equivalent(ops, b"Hello, world!", Vec::new, || Vec::with_capacity(64));
+/// ```
+pub fn assert_writers_equivalent<I, FA, A, FB, B>(ops: I, data: &[u8], make_a: FA, make_b: FB)
+    where I: IntoIterator<Item = PartialOp> + Clone,
+          FA: FnOnce() -> A,
+          A: Write + AsRef<[u8]>,
+          FB: FnOnce() -> B,
+          B: Write + AsRef<[u8]>
+{
+    let mut a = PartialWrite::new(make_a(), ops.clone());
+    let mut b = PartialWrite::new(make_b(), ops);
+    let res_a = write_all(&mut a, data);
+    let res_b = write_all(&mut b, data);
+    match (res_a, res_b) {
+        (Ok(()), Ok(())) => {}
+        (Err(ref err_a), Err(ref err_b)) if err_a.kind() == err_b.kind() => {}
+        (res_a, res_b) => panic!("writers ended differently: {:?} and {:?}", res_a, res_b),
+    }
+    assert_same_bytes(a.get_ref().as_ref(), b.get_ref().as_ref());
+}
+
+/// Writes out all of `data` and flushes, retrying on `Interrupted` errors.
+fn write_all<W: Write>(writer: &mut W, data: &[u8]) -> io::Result<()> {
+    let mut written = 0;
+    while written < data.len() {
+        match writer.write(&data[written..]) {
+            Ok(0) => {
+                let msg = format!("writer accepted no data after {} of {} bytes",
+                                  written,
+                                  data.len());
+                return Err(io::Error::new(io::ErrorKind::WriteZero, msg));
+            }
+            Ok(n) => written += n,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    loop {
+        match writer.flush() {
+            Ok(()) => return Ok(()),
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+fn assert_same_bytes(actual: &[u8], expected: &[u8]) {
+    if let Some(pos) = actual.iter().zip(expected).position(|(a, b)| a != b) {
+        panic!("bytes differ at offset {}: got {:#04x}, expected {:#04x}",
+               pos,
+               actual[pos],
+               expected[pos]);
+    }
+    if actual.len() != expected.len() {
+        panic!("got {} bytes, expected {}", actual.len(), expected.len());
+    }
+}
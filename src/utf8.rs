@@ -0,0 +1,57 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! This module contains helpers to split UTF-8 text across reads.
+
+use PartialOp;
+
+/// Computes a sequence of `PartialOp`s that splits `text` in the middle of
+/// every multi-byte character.
+///
+/// Reading `text` with these operations cuts each multi-byte character right
+/// after its first byte, so an incremental UTF-8 decoder has to hold on to the
+/// partial character until the rest of it arrives in the next read. Runs of
+/// ASCII text are read in as few reads as possible. This deliberately crosses
+/// character boundaries, which random `Limited` sizes only do occasionally.
+///
+/// The cuts land in the right places as long as every read is given a buffer
+/// at least as large as the chunk being read. A smaller buffer shifts all the
+/// cuts after it.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io::{Cursor, Read};
+///
+/// use partial_io::{PartialRead, utf8_split_ops};
+///
+/// let text = "añb€";
+/// let ops = utf8_split_ops(text);
+/// assert_eq!(format!("{:?}", ops), "[Limited(2), Limited(3), Limited(2)]");
+///
+/// let mut partial_reader = PartialRead::new(Cursor::new(text), ops);
+/// let mut out = [0; 16];
+/// assert_eq!(partial_reader.read(&mut out).unwrap(), 2);
+/// assert!(::std::str::from_utf8(&out[..2]).is_err());
+/// ```
+pub fn utf8_split_ops(text: &str) -> Vec<PartialOp> {
+    let mut ops = Vec::new();
+    let mut last_cut = 0;
+    for (index, ch) in text.char_indices() {
+        if ch.len_utf8() > 1 {
+            let cut = index + 1;
+            ops.push(PartialOp::Limited(cut - last_cut));
+            last_cut = cut;
+        }
+    }
+    if last_cut < text.len() {
+        ops.push(PartialOp::Limited(text.len() - last_cut));
+    }
+    ops
+}
@@ -10,11 +10,19 @@
 //! This module contains a writer wrapper that breaks writes up according to a
 //! provided iterator.
 
+use std::any::Any;
 use std::cmp;
-use std::io::{self, Write};
+use std::io::{self, IoSlice, Write};
 use std::iter::Fuse;
+use std::panic::UnwindSafe;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use PartialOp;
+use {ErrorCallback, Metrics, OpKind, PartialIoError, PartialOp, aligned_len, capped_slices,
+     trace_op};
+use monotonic::Monotonic;
+use pattern::ops_from_env;
+use rng::XorShift;
 
 /// A writer wrapper that breaks inner `Write` instances up according to the
 /// provided iterator.
@@ -40,6 +48,42 @@ pub struct PartialWrite<W, I>
 {
     inner: W,
     iter: Fuse<I::IntoIter>,
+    metrics: Metrics,
+    max_ops: Option<usize>,
+    on_injected_error: Option<ErrorCallback>,
+    write_sizes: Option<Vec<usize>>,
+    enabled: bool,
+    gate: Option<Arc<AtomicBool>>,
+    tee: Option<Box<dyn TeeSink>>,
+    monotonic: Option<Monotonic>,
+    recorded: Option<Vec<PartialOp>>,
+    // The generator and max_keep_fraction for random_short.
+    random_short: Option<(XorShift, f64)>,
+    watermark: Option<usize>,
+    // The error armed by ErrAfterFlush, and whether a flush has succeeded
+    // since.
+    err_after_flush: Option<(io::ErrorKind, bool)>,
+    // The number of bytes written but not yet drained, if there's a watermark.
+    pending: usize,
+}
+
+/// The sink for `PartialWrite::tee_writes`, which can be handed back as its
+/// concrete type.
+trait TeeSink: Write + Send + Sync + UnwindSafe {
+    fn as_any(&self) -> &dyn Any;
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<S> TeeSink for S
+    where S: Write + Send + Sync + UnwindSafe + 'static
+{
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
 }
 
 impl<W, I> PartialWrite<W, I>
@@ -48,11 +92,404 @@ impl<W, I> PartialWrite<W, I>
 {
     pub fn new(inner: W, iter: I) -> Self {
         PartialWrite {
-            inner: inner,
+            inner,
             // Use fuse here so that we don't keep calling the inner iterator
             // once it's returned None.
             iter: iter.into_iter().fuse(),
+            metrics: Metrics::default(),
+            max_ops: None,
+            on_injected_error: None,
+            write_sizes: None,
+            enabled: true,
+            gate: None,
+            tee: None,
+            monotonic: None,
+            recorded: None,
+            random_short: None,
+            watermark: None,
+            err_after_flush: None,
+            pending: 0,
+        }
+    }
+
+    /// Creates a new wrapper whose fault injection can be paused and resumed
+    /// from other threads through `gate`.
+    ///
+    /// This is the thread-safe counterpart of [`set_enabled`]. While `gate` is
+    /// `false`, every call passes straight through to the underlying writer
+    /// and no operations are taken from the iterator. This is useful for
+    /// pausing fault injection while a background task does something that
+    /// must succeed, like reconfiguring a connection.
+    ///
+    /// The gate is checked once at the start of every call, so changing it
+    /// takes effect from the next call on, never in the middle of one. Fault
+    /// injection only happens when both the gate is `true` and the wrapper
+    /// hasn't been disabled with `set_enabled`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::{self, Write};
+    /// use std::sync::Arc;
+    /// use std::sync::atomic::{AtomicBool, Ordering};
+    /// use std::thread;
+    ///
+    /// use partial_io::{PartialOp, PartialWrite};
+    ///
+    /// let gate = Arc::new(AtomicBool::new(false));
+    /// let ops = vec![PartialOp::Err(io::ErrorKind::Interrupted)];
+    /// let mut partial_writer = PartialWrite::new_gated(Vec::new(), ops, gate.clone());
+    /// assert_eq!(partial_writer.write(b"setup").unwrap(), 5);
+    ///
+    /// let other = gate.clone();
+    /// thread::spawn(move || other.store(true, Ordering::SeqCst)).join().unwrap();
+    /// assert_eq!(partial_writer.write(b"body").unwrap_err().kind(),
+    ///            io::ErrorKind::Interrupted);
+    /// ```
+    ///
+    /// [`set_enabled`]: #method.set_enabled
+    pub fn new_gated(inner: W, iter: I, gate: Arc<AtomicBool>) -> Self {
+        let mut writer = PartialWrite::new(inner, iter);
+        writer.gate = Some(gate);
+        writer
+    }
+
+    /// Duplicates every byte written to the underlying writer into `sink`.
+    ///
+    /// This is a debugging aid: it gives a byte-accurate trace of what made it
+    /// through to the underlying writer, in order, even if the underlying
+    /// writer later transforms or discards it. Only bytes that the underlying
+    /// writer reports as written are copied, so a short write only copies the
+    /// bytes that were accepted.
+    ///
+    /// Errors while writing to `sink` are ignored, so that the trace doesn't
+    /// change the behavior of the code under test. Calling this again replaces
+    /// the previous sink. No copying happens unless this is called. The sink
+    /// must be `Sync` and `UnwindSafe`, like the wrapper itself, which the
+    /// standard output streams and files are. Use [`tee_sink`] or
+    /// [`take_tee_sink`] to look at what was copied.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::{self, Write};
+    ///
+    /// use partial_io::{PartialOp, PartialWrite};
+    ///
+    /// let ops = vec![PartialOp::Limited(2), PartialOp::Err(io::ErrorKind::Interrupted)];
+    /// let mut partial_writer = PartialWrite::new(io::sink(), ops);
+    /// partial_writer.tee_writes(Vec::new());
+    /// partial_writer.write_all(b"Hello, world!").unwrap();
+    ///
+    /// let log: Vec<u8> = partial_writer.take_tee_sink().unwrap();
+    /// assert_eq!(&log[..], b"Hello, world!");
+    /// ```
+    ///
+    /// [`tee_sink`]: #method.tee_sink
+    /// [`take_tee_sink`]: #method.take_tee_sink
+    pub fn tee_writes<S>(&mut self, sink: S)
+        where S: Write + Send + Sync + UnwindSafe + 'static
+    {
+        self.tee = Some(Box::new(sink));
+    }
+
+    /// Returns a reference to the sink passed to [`tee_writes`].
+    ///
+    /// Returns `None` if there is no sink, or if it isn't of type `S`.
+    ///
+    /// [`tee_writes`]: #method.tee_writes
+    pub fn tee_sink<S>(&self) -> Option<&S>
+        where S: 'static
+    {
+        // Go through the box, which is a TeeSink itself.
+        self.tee.as_ref().and_then(|tee| (**tee).as_any().downcast_ref())
+    }
+
+    /// Stops copying writes, and returns the sink passed to [`tee_writes`].
+    ///
+    /// Returns `None`, and keeps copying, if there is no sink or if it isn't of
+    /// type `S`.
+    ///
+    /// [`tee_writes`]: #method.tee_writes
+    pub fn take_tee_sink<S>(&mut self) -> Option<S>
+        where S: 'static
+    {
+        match self.tee {
+            Some(ref tee) if (**tee).as_any().is::<S>() => {}
+            _ => return None,
         }
+        self.tee
+            .take()
+            .and_then(|tee| tee.into_any().downcast().ok())
+            .map(|sink| *sink)
+    }
+
+    /// Panics if the bytes forwarded to the underlying writer aren't exactly
+    /// the bytes reported to the caller as written, in order.
+    ///
+    /// This is a debugging aid for the wrapper's own limiting logic. From now
+    /// on, the wrapper tracks the offset of the next byte the underlying
+    /// writer should receive, and panics if a write forwards data from any
+    /// other offset, or if a call reports a different number of bytes written
+    /// than actually reached the underlying writer. Since either would leave
+    /// gaps or repeats in the output, this catches miscounting as soon as it
+    /// happens rather than when the output is compared.
+    ///
+    /// `PartialOp::ReportExactly` misreports on purpose, so the check accepts
+    /// the positions after it as correct. No tracking happens unless this is
+    /// called.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::Write;
+    ///
+    /// use partial_io::{PartialOp, PartialWrite};
+    ///
+    /// let ops = vec![PartialOp::Limited(2), PartialOp::AlignedTo(4)];
+    /// let mut partial_writer = PartialWrite::new(Vec::new(), ops);
+    /// partial_writer.verify_monotonic();
+    /// partial_writer.write_all(b"Hello, world!").unwrap();
+    /// assert_eq!(&partial_writer.get_ref()[..], b"Hello, world!");
+    /// ```
+    pub fn verify_monotonic(&mut self) {
+        self.monotonic = Some(Monotonic::default());
+    }
+
+    /// Makes this wrapper behave like a bounded send buffer holding up to
+    /// `limit` bytes.
+    ///
+    /// Every byte written counts against the limit until it is freed with
+    /// [`drain`]. Writes are cut short so that no more than `limit` bytes are
+    /// pending at once, and once the limit is reached, writes fail with
+    /// `WouldBlock` without taking an operation from the iterator. This is
+    /// useful for testing producers that must respect backpressure: unlike a
+    /// scripted `WouldBlock`, when the writer blocks depends on how much has
+    /// been written and drained so far.
+    ///
+    /// Operations still apply to writes that go through. Flushes aren't
+    /// affected by the watermark, and neither are writes made while fault
+    /// injection is disabled: those pass straight through and don't count
+    /// against the limit.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::{self, Write};
+    ///
+    /// use partial_io::PartialWrite;
+    ///
+    /// let mut partial_writer = PartialWrite::new(Vec::new(), vec![]).with_watermark(4);
+    /// assert_eq!(partial_writer.write(b"Hello").unwrap(), 4);
+    /// assert_eq!(partial_writer.write(b"o").unwrap_err().kind(), io::ErrorKind::WouldBlock);
+    ///
+    /// partial_writer.drain(2);
+    /// assert_eq!(partial_writer.pending(), 2);
+    /// assert_eq!(partial_writer.write(b"o, world!").unwrap(), 2);
+    /// assert_eq!(partial_writer.get_ref(), b"Hello,");
+    /// ```
+    ///
+    /// [`drain`]: #method.drain
+    pub fn with_watermark(mut self, limit: usize) -> Self {
+        self.watermark = Some(limit);
+        self
+    }
+
+    /// Frees up `n` bytes of capacity below the watermark.
+    ///
+    /// This models the other end of the connection consuming data. Draining
+    /// more than is pending just empties the buffer. This has no effect if
+    /// there is no watermark.
+    pub fn drain(&mut self, n: usize) {
+        self.pending = self.pending.saturating_sub(n);
+    }
+
+    /// Returns the number of bytes written but not yet drained.
+    ///
+    /// This is always 0 if there is no watermark.
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// Calls `callback` every time this wrapper returns an error that it
+    /// injected.
+    ///
+    /// This is for checking that the code under test's error handling
+    /// actually ran, rather than errors being swallowed. Count the calls, and
+    /// compare the count against what the code under test reported, for
+    /// example how many errors it logged or returned. Every injected error is
+    /// passed, including ones that the code under test is right to recover
+    /// from by retrying, like `Interrupted` or `WouldBlock`, so filter on
+    /// `err.kind()` to only count the ones that should reach the end.
+    ///
+    /// Errors from the underlying writer are not passed. Calling this again
+    /// replaces the previous callback. The callback must be `Sync` and
+    /// `UnwindSafe`, like the wrapper itself, which closures over atomics or
+    /// mutexes are.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::{self, Write};
+    /// use std::sync::Arc;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    ///
+    /// use partial_io::{PartialOp, PartialWrite};
+    ///
+    /// // Returns how many errors were reported.
+    /// fn save<W: Write>(writer: &mut W) -> usize {
+    ///     let mut reported = 0;
+    ///     // Bug: the result of the first write is ignored.
+    ///     let _ = writer.write_all(b"ab");
+    ///     if writer.write_all(b"cd").is_err() {
+    ///         reported += 1;
+    ///     }
+    ///     reported
+    /// }
+    ///
+    /// let ops = vec![PartialOp::Err(io::ErrorKind::Other); 2];
+    /// let mut partial_writer = PartialWrite::new(Vec::new(), ops);
+    /// let injected = Arc::new(AtomicUsize::new(0));
+    /// let counter = injected.clone();
+    /// partial_writer.on_injected_error(move |_| {
+    ///     counter.fetch_add(1, Ordering::SeqCst);
+    /// });
+    ///
+    /// let reported = save(&mut partial_writer);
+    /// // One of the two errors was swallowed.
+    /// assert_eq!(injected.load(Ordering::SeqCst), 2);
+    /// assert_eq!(reported, 1);
+    /// ```
+    pub fn on_injected_error<F>(&mut self, callback: F)
+        where F: FnMut(&io::Error) + Send + Sync + UnwindSafe + 'static
+    {
+        self.on_injected_error = Some(Box::new(callback));
+    }
+
+    /// Makes every call fail once more than `max_ops` calls have been made.
+    ///
+    /// This is a guardrail against runaway tests: if the code under test gets
+    /// stuck in a loop, it fails fast with an `Other` error saying that the
+    /// partial-io operation limit was exceeded, instead of spinning forever.
+    /// Every `write`, `write_vectored` and `flush` call counts towards the
+    /// limit, whatever operation it ends up with. Once the limit is exceeded,
+    /// calls no longer reach the underlying writer or take operations from the
+    /// iterator.
+    ///
+    /// There is no limit by default.
+    pub fn set_max_ops(&mut self, max_ops: usize) {
+        self.max_ops = Some(max_ops);
+    }
+
+    /// Enables or disables fault injection.
+    ///
+    /// While disabled, every call passes straight through to the underlying
+    /// writer and no operations are taken from the iterator. Re-enabling picks
+    /// up from the operation after the last one taken. Wrappers start out
+    /// enabled.
+    ///
+    /// This is useful for tests with distinct phases, like writing a preamble
+    /// that must succeed before the part under test.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::{self, Write};
+    ///
+    /// use partial_io::{PartialOp, PartialWrite};
+    ///
+    /// let ops = vec![PartialOp::Err(io::ErrorKind::Interrupted)];
+    /// let mut partial_writer = PartialWrite::new(Vec::new(), ops);
+    ///
+    /// partial_writer.set_enabled(false);
+    /// assert_eq!(partial_writer.write(b"preamble").unwrap(), 8);
+    ///
+    /// partial_writer.set_enabled(true);
+    /// assert_eq!(partial_writer.write(b"body").unwrap_err().kind(),
+    ///            io::ErrorKind::Interrupted);
+    /// ```
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Returns whether fault injection is enabled.
+    ///
+    /// This only reflects `set_enabled`, not the gate passed to
+    /// [`new_gated`](#method.new_gated).
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Returns a snapshot of the counters kept by this wrapper.
+    ///
+    /// See [`Metrics`](struct.Metrics.html) for more.
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.clone()
+    }
+
+    /// Starts recording the size of the buffer passed into each `write` call.
+    ///
+    /// The sizes are returned by [`inner_write_sizes`]. Nothing is recorded
+    /// unless this is called, so that long streams don't build up an
+    /// ever-growing list.
+    ///
+    /// [`inner_write_sizes`]: #method.inner_write_sizes
+    pub fn record_write_sizes(&mut self) {
+        if self.write_sizes.is_none() {
+            self.write_sizes = Some(Vec::new());
+        }
+    }
+
+    /// Returns the size of the buffer passed into each `write` call since
+    /// [`record_write_sizes`] was called, in order.
+    ///
+    /// This records what the code under test asked to write, before any
+    /// operations are applied. It is useful for checking how calls are
+    /// batched: for example, that a buffered writer wrote `[4096, 4096, 128]`.
+    /// Returns an empty slice if sizes aren't being recorded.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::{BufWriter, Write};
+    ///
+    /// use partial_io::{PartialOp, PartialWrite};
+    ///
+    /// let mut partial_writer = PartialWrite::new(Vec::new(), vec![PartialOp::Limited(2)]);
+    /// partial_writer.record_write_sizes();
+    /// let mut buf_writer = BufWriter::with_capacity(4, partial_writer);
+    /// buf_writer.write_all(b"abc").unwrap();
+    /// buf_writer.write_all(b"def").unwrap();
+    /// buf_writer.flush().unwrap();
+    ///
+    /// // The first flush of the internal buffer was cut short after 2 bytes.
+    /// assert_eq!(buf_writer.get_ref().inner_write_sizes(), &[3, 1, 3]);
+    /// ```
+    ///
+    /// [`record_write_sizes`]: #method.record_write_sizes
+    pub fn inner_write_sizes(&self) -> &[usize] {
+        match self.write_sizes {
+            Some(ref sizes) => sizes,
+            None => &[],
+        }
+    }
+
+    /// Returns the operations recorded so far by a wrapper created with
+    /// [`recorder`], or `None` if this wrapper isn't recording.
+    ///
+    /// [`recorder`]: #method.recorder
+    pub fn recorded_ops(&self) -> Option<&[PartialOp]> {
+        self.recorded.as_ref().map(|ops| &ops[..])
+    }
+
+    /// Returns whether the most recent call applied an operation from the
+    /// iterator, or passed straight through to the underlying writer.
+    ///
+    /// Returns `None` if no calls have been made yet. See
+    /// [`Metrics::last_op_injected`](struct.Metrics.html#structfield.last_op_injected)
+    /// for more.
+    pub fn last_op_was_injected(&self) -> Option<bool> {
+        self.metrics.last_op_injected
     }
 
     /// Acquires a reference to the underlying writer.
@@ -69,6 +506,367 @@ impl<W, I> PartialWrite<W, I>
     pub fn into_inner(self) -> W {
         self.inner
     }
+
+    fn write_inner_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+        let res = self.metrics.transferred(self.inner.write_vectored(bufs));
+        if let (true, &Ok(n)) = (self.is_forwarding(), &res) {
+            let mut left = n;
+            for buf in bufs {
+                let len = cmp::min(left, buf.len());
+                self.forwarded(n - left, &buf[..len]);
+                left -= len;
+            }
+        }
+        res
+    }
+
+    fn write_inner(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let res = self.metrics.transferred(self.inner.write(buf));
+        if let (true, &Ok(n)) = (self.is_forwarding(), &res) {
+            // The underlying writer might report more bytes than it was given.
+            self.forwarded(0, &buf[..cmp::min(n, buf.len())]);
+        }
+        res
+    }
+
+    /// Returns whether the bytes accepted by the underlying writer need to be
+    /// passed to `forwarded`.
+    fn is_forwarding(&self) -> bool {
+        self.tee.is_some() || self.monotonic.is_some()
+    }
+
+    /// Copies `data`, which the underlying writer accepted `skip` bytes into
+    /// the current call, to the tee and checks it against the monotonic
+    /// position.
+    fn forwarded(&mut self, skip: usize, data: &[u8]) {
+        if let Some(tee) = self.tee.as_mut() {
+            let _ = tee.write_all(data);
+        }
+        if let Some(monotonic) = self.monotonic.as_mut() {
+            let offset = monotonic.accepted_len() + skip as u64;
+            monotonic.forwarded(offset, data);
+        }
+    }
+
+    /// Checks the number of bytes reported by a `write` call against what
+    /// reached the underlying writer, if verifying.
+    fn verify_reported(&mut self, op: &Option<PartialOp>, res: &io::Result<usize>) {
+        if let (Some(monotonic), &Ok(n)) = (self.monotonic.as_mut(), res) {
+            match *op {
+                Some(PartialOp::ReportExactly(_)) => monotonic.resync(),
+                _ => {
+                    monotonic.accepted(n);
+                    monotonic.check_forwarded();
+                }
+            }
+        }
+    }
+
+    /// Returns how many of `len` bytes fit below the watermark.
+    fn writable_len(&self, len: usize) -> usize {
+        match self.watermark {
+            Some(limit) if self.is_injecting() => {
+                cmp::min(len, limit.saturating_sub(self.pending))
+            }
+            _ => len,
+        }
+    }
+
+    /// Returns how many of `len` bytes to pass to `write_op`.
+    fn write_len(&mut self, op: &Option<PartialOp>, len: usize) -> usize {
+        let len = self.writable_len(len);
+        let injecting = len > 0 && self.is_injecting();
+        match (op, self.random_short.as_mut()) {
+            (&None, Some(&mut (ref mut rng, max_keep_fraction))) if injecting => {
+                let max = cmp::max(1, (len as f64 * max_keep_fraction) as usize);
+                1 + rng.below(max)
+            }
+            _ => len,
+        }
+    }
+
+    /// Returns the error for a write of `len` bytes if the watermark has been
+    /// reached.
+    fn watermark_err(&mut self, len: usize) -> Option<io::Error> {
+        if len == 0 || !self.is_injecting() || self.writable_len(len) > 0 {
+            return None;
+        }
+        Some(self.metrics.injected_by_wrapper(io::ErrorKind::WouldBlock,
+                                              OpKind::Write,
+                                              "watermark reached during write, \
+                                               generated by partial-io"))
+    }
+
+    /// Returns the error armed by `ErrAfterFlush`, if a flush has succeeded
+    /// since it was armed.
+    fn after_flush_err(&mut self) -> Option<io::Error> {
+        match self.err_after_flush {
+            Some((kind, true)) if self.is_injecting() => {
+                self.err_after_flush = None;
+                // The operation was taken earlier, so none is taken now.
+                Some(self.metrics.injected_by_wrapper(kind,
+                                                      OpKind::Write,
+                                                      "error after flush, generated by partial-io"))
+            }
+            _ => None,
+        }
+    }
+
+    /// Lowers `caps` so that a vectored write of `bufs` fits below the
+    /// watermark.
+    fn watermark_caps(&self, bufs: &[IoSlice], caps: &[usize]) -> Vec<usize> {
+        let mut left = self.writable_len(usize::MAX);
+        bufs.iter()
+            .zip(caps)
+            .map(|(buf, &cap)| {
+                     let cap = cmp::min(cap, left);
+                     left -= cmp::min(cap, buf.len());
+                     cap
+                 })
+            .collect()
+    }
+
+    /// Counts the bytes of a successful write against the watermark.
+    fn fill(&mut self, res: io::Result<usize>) -> io::Result<usize> {
+        if let (Some(_), &Ok(n)) = (self.watermark, &res) {
+            if self.is_injecting() {
+                self.pending += n;
+            }
+        }
+        res
+    }
+
+    /// Applies `op` to a `write` call for `buf`.
+    fn write_op(&mut self, op: &Option<PartialOp>, buf: &[u8]) -> io::Result<usize> {
+        match *op {
+            Some(PartialOp::Limited(n)) => {
+                let len = cmp::min(n, buf.len());
+                self.write_inner(&buf[..len])
+            }
+            Some(PartialOp::AlignedTo(align)) => {
+                let len = aligned_len(buf.len(), align);
+                if len == 0 && !buf.is_empty() {
+                    Err(self.metrics.injected(io::ErrorKind::InvalidInput,
+                                              OpKind::Write,
+                                              "buffer smaller than alignment during write, \
+                                               generated by partial-io"))
+                } else {
+                    self.write_inner(&buf[..len])
+                }
+            }
+            Some(PartialOp::ReportExactly(n)) => {
+                self.write_inner(buf).map(|_| n)
+            }
+            Some(PartialOp::Err(err)) => {
+                Err(self.metrics.injected(err,
+                                          OpKind::Write,
+                                          "error during write, generated by partial-io"))
+            }
+            Some(PartialOp::VectoredLimited(ref caps)) => {
+                let len = cmp::min(caps.first().cloned().unwrap_or(0), buf.len());
+                self.write_inner(&buf[..len])
+            }
+            Some(PartialOp::AsyncWriteZero) => Ok(0),
+            Some(PartialOp::ErrAfterFlush(kind)) => {
+                self.err_after_flush = Some((kind, false));
+                self.write_inner(buf)
+            }
+            // Only PartialAsyncWrite supports this.
+            Some(PartialOp::DropAfter(_)) |
+            // Direction-specific operations have already been resolved.
+            Some(PartialOp::Duplicate) |
+            Some(PartialOp::ErrOnLast(_)) |
+            Some(PartialOp::ReadLimited(_)) |
+            Some(PartialOp::WriteLimited(_)) |
+            Some(PartialOp::ReadErr(_)) |
+            Some(PartialOp::WriteErr(_)) |
+            Some(PartialOp::Unlimited) |
+            None => self.write_inner(buf),
+        }
+    }
+
+    /// Appends the operation that reproduces the result of a `write` call for
+    /// a buffer of length `buf_len`, if recording.
+    fn record_write(&mut self, buf_len: usize, res: &io::Result<usize>) {
+        if let Some(recorded) = self.recorded.as_mut() {
+            recorded.push(match *res {
+                Ok(n) if n < buf_len => PartialOp::Limited(n),
+                Ok(_) => PartialOp::Unlimited,
+                Err(ref err) => PartialOp::Err(err.kind()),
+            });
+        }
+    }
+
+    /// Appends the operation that reproduces the result of a `flush` call, if
+    /// recording.
+    fn record_flush(&mut self, res: &io::Result<()>) {
+        if let Some(recorded) = self.recorded.as_mut() {
+            recorded.push(match *res {
+                Ok(()) => PartialOp::Unlimited,
+                Err(ref err) => PartialOp::Err(err.kind()),
+            });
+        }
+    }
+
+    /// Passes `res` to the `on_injected_error` callback if it is an injected
+    /// error.
+    fn returned<T>(&mut self, res: io::Result<T>) -> io::Result<T> {
+        if let (Err(err), Some(callback)) = (&res, self.on_injected_error.as_mut()) {
+            if let Some(true) = PartialIoError::from_io(err).map(PartialIoError::is_injected) {
+                callback(err);
+            }
+        }
+        res
+    }
+
+    /// Returns whether faults should be injected, according to `set_enabled`
+    /// and the gate.
+    fn is_injecting(&self) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        match self.gate {
+            Some(ref gate) => gate.load(Ordering::SeqCst),
+            None => true,
+        }
+    }
+
+    /// Appends `len` to the recorded write sizes, if recording.
+    fn record_write_size(&mut self, len: usize) {
+        if let Some(sizes) = self.write_sizes.as_mut() {
+            sizes.push(len);
+        }
+    }
+
+    fn next_op(&mut self) -> Option<PartialOp> {
+        let op = if self.is_injecting() {
+            self.iter.next().map(PartialOp::for_write)
+        } else {
+            None
+        };
+        self.metrics.op(op)
+    }
+}
+
+impl<W> PartialWrite<W, Vec<PartialOp>>
+    where W: Write
+{
+    /// Creates a new wrapper that doesn't inject anything, and instead records
+    /// what the underlying writer actually did.
+    ///
+    /// Every call passes straight through to the underlying writer, and its
+    /// result is recorded as the operation that would reproduce it: a short
+    /// write becomes `Limited(n)`, a complete write or a successful flush
+    /// becomes `Unlimited`, and an error becomes `Err(kind)`. The recorded
+    /// operations are available through [`recorded_ops`].
+    ///
+    /// This allows for capture-then-replay testing: wrap a real socket or file
+    /// in a recorder, save the operations, and later pass them to
+    /// `PartialWrite::new` in front of an in-memory writer to reproduce the same
+    /// sequence of short writes and errors. Error kinds are recorded, but not
+    /// error messages.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::Write;
+    ///
+    /// use partial_io::{PartialOp, PartialWrite};
+    ///
+    /// // Stand in for a socket that only accepts 2 bytes at a time.
+    /// let socket = PartialWrite::new(Vec::new(), vec![PartialOp::Limited(2)]);
+    ///
+    /// let mut recorder = PartialWrite::recorder(socket);
+    /// recorder.write_all(&[1, 2, 3, 4]).unwrap();
+    /// recorder.flush().unwrap();
+    ///
+    /// let ops = recorder.recorded_ops().unwrap().to_vec();
+    /// assert_eq!(format!("{:?}", ops), "[Limited(2), Unlimited, Unlimited]");
+    ///
+    /// // Replay the same behavior without the socket.
+    /// let mut replay = PartialWrite::new(Vec::new(), ops);
+    /// assert_eq!(replay.write(&[1, 2, 3, 4]).unwrap(), 2);
+    /// ```
+    ///
+    /// [`recorded_ops`]: #method.recorded_ops
+    pub fn recorder(inner: W) -> Self {
+        let mut writer = PartialWrite::new(inner, Vec::new());
+        writer.recorded = Some(Vec::new());
+        writer
+    }
+
+    /// Creates a new wrapper that cuts every write short by a random amount.
+    ///
+    /// Each `write` of a non-empty buffer writes between 1 byte and
+    /// `max_keep_fraction` of the buffer, rounded down but at least 1 byte. It
+    /// never writes 0 bytes, so code that loops until everything is written
+    /// always makes progress. The lengths come from a pseudo-random number
+    /// generator owned by the wrapper and seeded with `seed`, so the same seed
+    /// and the same writes give the same results. Nothing is precomputed, so
+    /// this works for arbitrarily long streams.
+    ///
+    /// This doesn't apply while fault injection is disabled. Flushes pass
+    /// straight through.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_keep_fraction` isn't greater than 0 and at most 1.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::io::Write;
+    ///
+    /// use partial_io::PartialWrite;
+    ///
+    /// let mut partial_writer = PartialWrite::random_short(Vec::new(), 42, 0.5);
+    /// let n = partial_writer.write(&[0; 100]).unwrap();
+    /// assert!(n >= 1 && n <= 50);
+    ///
+    /// partial_writer.write_all(&[1; 1000]).unwrap();
+    /// assert_eq!(partial_writer.get_ref().len(), n + 1000);
+    /// ```
+    pub fn random_short(inner: W, seed: u64, max_keep_fraction: f64) -> Self {
+        assert!(max_keep_fraction > 0.0 && max_keep_fraction <= 1.0,
+                "max_keep_fraction must be greater than 0 and at most 1");
+        let mut writer = PartialWrite::new(inner, Vec::new());
+        writer.random_short = Some((XorShift::new(seed), max_keep_fraction));
+        writer
+    }
+
+    /// Creates a new wrapper with operations taken from the
+    /// `PARTIAL_IO_DEFAULT_OPS` environment variable, or `default_ops` if it
+    /// isn't set.
+    ///
+    /// The variable is parsed with [`ops_from_pattern`]. This makes it possible
+    /// to run an entire test binary under different fault profiles without
+    /// changing any code, e.g. `PARTIAL_IO_DEFAULT_OPS="L1 W L3" cargo test`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the environment variable is set but is not a valid pattern.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::env;
+    /// use std::io::Write;
+    ///
+    /// use partial_io::{DEFAULT_OPS_ENV, PartialOp, PartialWrite};
+    ///
+    /// let mut partial_writer = PartialWrite::from_env_or(Vec::new(), vec![PartialOp::Limited(1)]);
+    /// // This only holds for the default operations, so that the example still
+    /// // passes when the tests are run with a fault profile.
+    /// if env::var_os(DEFAULT_OPS_ENV).is_none() {
+    ///     assert_eq!(partial_writer.write(&[1, 2, 3, 4]).unwrap(), 1);
+    /// }
+    /// ```
+    ///
+    /// [`ops_from_pattern`]: fn.ops_from_pattern.html
+    pub fn from_env_or(inner: W, default_ops: Vec<PartialOp>) -> Self {
+        let ops = ops_from_env().unwrap_or(default_ops);
+        PartialWrite::new(inner, ops)
+    }
 }
 
 impl<W, I> Write for PartialWrite<W, I>
@@ -76,25 +874,99 @@ impl<W, I> Write for PartialWrite<W, I>
           I: IntoIterator<Item = PartialOp>
 {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        match self.iter.next() {
-            Some(PartialOp::Limited(n)) => {
-                let len = cmp::min(n, buf.len());
-                self.inner.write(&buf[..len])
+        self.metrics.call(buf.len());
+        self.record_write_size(buf.len());
+        let err = self.metrics
+            .limit_exceeded(self.max_ops, OpKind::Write)
+            .or_else(|| self.watermark_err(buf.len()))
+            .or_else(|| self.after_flush_err());
+        let (op, res) = match err {
+            Some(err) => (None, Err(err)),
+            None => {
+                let op = self.next_op();
+                let len = self.write_len(&op, buf.len());
+                let res = self.write_op(&op, &buf[..len]);
+                (op, self.fill(res))
             }
-            Some(PartialOp::Err(err)) => {
-                Err(io::Error::new(err, "error during write, generated by partial-io"))
+        };
+        self.verify_reported(&op, &res);
+        self.record_write(buf.len(), &res);
+        trace_op(OpKind::Write, &self.metrics, &op, Some(buf.len()), &res);
+        self.returned(res)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+        let total = bufs.iter().map(|buf| buf.len()).sum();
+        self.metrics.call(total);
+        self.record_write_size(total);
+        let err = self.metrics
+            .limit_exceeded(self.max_ops, OpKind::Write)
+            .or_else(|| self.watermark_err(total))
+            .or_else(|| self.after_flush_err());
+        let (op, res) = match err {
+            Some(err) => (None, Err(err)),
+            None => {
+                let op = self.next_op();
+                let res = match op {
+                    Some(PartialOp::VectoredLimited(ref caps)) => {
+                        let caps = self.watermark_caps(bufs, caps);
+                        self.write_inner_vectored(&capped_slices(bufs, &caps))
+                    }
+                    // Like the default implementation, only write out the
+                    // first non-empty buffer.
+                    _ => {
+                        let buf = bufs.iter()
+                            .find(|buf| !buf.is_empty())
+                            .map_or(&[][..], |buf| &**buf);
+                        let len = self.write_len(&op, buf.len());
+                        self.write_op(&op, &buf[..len])
+                    }
+                };
+                (op, self.fill(res))
             }
-            Some(PartialOp::Unlimited) |
-            None => self.inner.write(buf),
-        }
+        };
+        self.verify_reported(&op, &res);
+        self.record_write(total, &res);
+        trace_op(OpKind::Write, &self.metrics, &op, Some(total), &res);
+        self.returned(res)
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        match self.iter.next() {
+        self.metrics.flush();
+        if let Some(err) = self.metrics.limit_exceeded(self.max_ops, OpKind::Flush) {
+            let res = Err(err);
+            trace_op(OpKind::Flush, &self.metrics, &None, None, &res);
+            return self.returned(res);
+        }
+        let op = self.next_op();
+        let res = match op {
             Some(PartialOp::Err(err)) => {
-                Err(io::Error::new(err, "error during flush, generated by partial-io"))
+                Err(self.metrics.injected(err,
+                                          OpKind::Flush,
+                                          "error during flush, generated by partial-io"))
             }
-            _ => self.inner.flush(),
-        }
+            _ => {
+                if let Some(PartialOp::ErrAfterFlush(kind)) = op {
+                    self.err_after_flush = Some((kind, false));
+                }
+                let res = self.metrics.inner(self.inner.flush());
+                if res.is_ok() {
+                    if let Some(tee) = self.tee.as_mut() {
+                        let _ = tee.flush();
+                    }
+                    // Flushes made while disabled pass straight through, so
+                    // they don't arm the error.
+                    if self.is_injecting() {
+                        if let Some((_, ref mut flushed)) = self.err_after_flush {
+                            *flushed = true;
+                        }
+                    }
+                }
+                res
+            }
+        };
+        self.record_flush(&res);
+        trace_op(OpKind::Flush, &self.metrics, &op, None, &res);
+        self.returned(res)
     }
 }
@@ -0,0 +1,57 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! Tests that the async wrappers can be driven by `Future::wait`, without an
+//! event loop.
+
+#![cfg(feature = "tokio")]
+
+extern crate futures;
+extern crate partial_io;
+extern crate tokio_io;
+
+use std::io::{self, Cursor};
+
+use futures::Future;
+use tokio_io::io::{read_exact, write_all};
+
+use partial_io::{PartialAsyncRead, PartialAsyncWrite, PartialOp};
+
+fn ops() -> Vec<PartialOp> {
+    (0..20)
+        .map(|i| if i % 3 == 0 {
+                 PartialOp::Err(io::ErrorKind::WouldBlock)
+             } else {
+                 PartialOp::Limited(1)
+             })
+        .collect()
+}
+
+#[test]
+fn write_all_wait() {
+    let partial_writer = PartialAsyncWrite::collecting(ops());
+    let (partial_writer, _) = write_all(partial_writer, b"Hello, world!").wait().unwrap();
+
+    assert_eq!(partial_writer.collected(), b"Hello, world!");
+    assert_eq!(partial_writer.metrics().errors_injected, 7);
+    // The polls that returned WouldBlock never touched the cursor.
+    assert_eq!(partial_writer.metrics().calls, 20);
+    assert_eq!(partial_writer.inner_io_calls(), 13);
+}
+
+#[test]
+fn read_exact_wait() {
+    let partial_reader = PartialAsyncRead::new(Cursor::new(b"Hello, world!".to_vec()), ops());
+    let (partial_reader, out) = read_exact(partial_reader, [0; 13]).wait().unwrap();
+
+    assert_eq!(&out, b"Hello, world!");
+    assert_eq!(partial_reader.metrics().errors_injected, 7);
+    assert_eq!(partial_reader.metrics().calls, 20);
+    assert_eq!(partial_reader.inner_io_calls(), 13);
+}
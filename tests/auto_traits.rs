@@ -0,0 +1,41 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! Tests that the wrappers are `Send`, `Sync` and `UnwindSafe` whenever what
+//! they wrap is.
+
+extern crate partial_io;
+
+use std::panic::UnwindSafe;
+
+use partial_io::{PartialOp, PartialRead, PartialWrite};
+
+fn assert_auto_traits<T: Send + Sync + UnwindSafe>() {}
+
+#[test]
+fn partial_read() {
+    assert_auto_traits::<PartialRead<Vec<u8>, Vec<PartialOp>>>();
+}
+
+#[test]
+fn partial_write() {
+    assert_auto_traits::<PartialWrite<Vec<u8>, Vec<PartialOp>>>();
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn async_wrappers() {
+    use std::io::Cursor;
+
+    use partial_io::{CoalescingPartialAsyncWrite, PartialAsyncRead, PartialAsyncWrite};
+
+    assert_auto_traits::<PartialAsyncRead<Cursor<Vec<u8>>, Vec<PartialOp>>>();
+    assert_auto_traits::<PartialAsyncWrite<Cursor<Vec<u8>>, Vec<PartialOp>>>();
+    assert_auto_traits::<CoalescingPartialAsyncWrite<Cursor<Vec<u8>>, Vec<PartialOp>>>();
+}
@@ -0,0 +1,75 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! Tests that `std::io::BufWriter` retries partial flushes of its internal
+//! buffer when wrapped around a partial writer.
+
+extern crate partial_io;
+
+use std::io::{self, BufWriter, Write};
+use std::iter;
+
+use partial_io::{PartialOp, PartialWrite};
+use partial_io::test::assert_writer_drains;
+
+fn test_data() -> Vec<u8> {
+    (0..1000u32).map(|i| (i % 251) as u8).collect()
+}
+
+#[test]
+fn buf_writer_one_byte_at_a_time() {
+    let data = test_data();
+    let partial_writer = PartialWrite::new(Vec::new(), iter::repeat(PartialOp::Limited(1)));
+    let mut buf_writer = BufWriter::with_capacity(64, partial_writer);
+
+    // Mix writes smaller than the capacity, which get buffered, with writes
+    // larger than it, which go straight to the inner writer.
+    for chunk in data.chunks(47).chain(Some(&data[..200])) {
+        buf_writer.write_all(chunk).unwrap();
+    }
+    buf_writer.flush().unwrap();
+
+    let partial_writer = buf_writer.get_ref();
+    let mut expected = data.clone();
+    expected.extend_from_slice(&data[..200]);
+    assert_eq!(partial_writer.get_ref(), &expected);
+    assert_eq!(partial_writer.metrics().max_transfer, 1);
+    // Write sizes are only recorded on request.
+    assert!(partial_writer.inner_write_sizes().is_empty());
+}
+
+#[test]
+fn buf_writer_interrupted() {
+    let data = test_data();
+    let ops = iter::repeat(PartialOp::Limited(3))
+        .zip(iter::repeat(PartialOp::Err(io::ErrorKind::Interrupted)))
+        .flat_map(|(a, b)| vec![a, b]);
+    let partial_writer = PartialWrite::new(Vec::new(), ops);
+    let mut buf_writer = BufWriter::with_capacity(64, partial_writer);
+
+    for chunk in data.chunks(10) {
+        buf_writer.write_all(chunk).unwrap();
+    }
+    // BufWriter retries interrupted writes while draining its buffer, but
+    // passes through an interrupted flush of the inner writer.
+    loop {
+        match buf_writer.flush() {
+            Ok(()) => break,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => panic!("flush failed: {}", err),
+        }
+    }
+
+    assert_eq!(buf_writer.get_ref().get_ref(), &data);
+}
+
+#[test]
+fn buf_writer_drains() {
+    assert_writer_drains(|w| BufWriter::with_capacity(64, w), &test_data());
+}
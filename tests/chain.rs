@@ -0,0 +1,66 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! Tests that partial readers compose with `Read::chain`.
+
+extern crate partial_io;
+
+use std::io::{self, Cursor, Read};
+
+use partial_io::{PartialOp, PartialRead};
+
+#[test]
+fn chain_moves_on_at_eof() {
+    let first = PartialRead::new(Cursor::new(b"Hello, ".to_vec()),
+                                 vec![PartialOp::Limited(3), PartialOp::Limited(5)]);
+    let second = PartialRead::new(Cursor::new(b"world!".to_vec()),
+                                  vec![PartialOp::Err(io::ErrorKind::Interrupted),
+                                       PartialOp::Limited(2)]);
+    let mut chain = first.chain(second);
+    let mut out = [0; 64];
+
+    // The first reader's operations only limit the first reader.
+    assert_eq!(chain.read(&mut out).unwrap(), 3);
+    assert_eq!(chain.read(&mut out[3..]).unwrap(), 4);
+    // The first reader passes through its EOF, so chain moves on to the
+    // second reader, whose operations start from the beginning.
+    assert_eq!(chain.read(&mut out[7..]).unwrap_err().kind(),
+               io::ErrorKind::Interrupted);
+    assert_eq!(chain.read(&mut out[7..]).unwrap(), 2);
+    assert_eq!(chain.read(&mut out[9..]).unwrap(), 4);
+    assert_eq!(chain.read(&mut out[13..]).unwrap(), 0);
+    assert_eq!(&out[..13], b"Hello, world!");
+
+    let (first, second) = chain.into_inner();
+    assert_eq!(first.metrics().ops_consumed, 2);
+    assert_eq!(second.metrics().ops_consumed, 2);
+}
+
+#[test]
+fn chain_read_to_end() {
+    let first = PartialRead::new(Cursor::new(b"Hello, ".to_vec()),
+                                 ::std::iter::repeat(PartialOp::Limited(1)));
+    let second = PartialRead::new(Cursor::new(b"world!".to_vec()),
+                                  ::std::iter::repeat(PartialOp::Limited(2)));
+    let mut out = Vec::new();
+    first.chain(second).read_to_end(&mut out).unwrap();
+    assert_eq!(&out, b"Hello, world!");
+}
+
+#[test]
+fn chain_limited_zero_is_eof() {
+    // Limited(0) makes a read return Ok(0), which is an EOF as far as chain is
+    // concerned. Anything left in the first reader is skipped.
+    let first = PartialRead::new(Cursor::new(b"Hello, ".to_vec()),
+                                 vec![PartialOp::Limited(0)]);
+    let second = Cursor::new(b"world!".to_vec());
+    let mut out = Vec::new();
+    first.chain(second).read_to_end(&mut out).unwrap();
+    assert_eq!(&out, b"world!");
+}
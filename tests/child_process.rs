@@ -0,0 +1,56 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! Tests that the wrappers work over a child process's pipes, which can't be
+//! seeked or cloned.
+
+#![cfg(unix)]
+
+extern crate partial_io;
+
+use std::io::{Read, Write};
+use std::iter;
+use std::process::{Command, Stdio};
+use std::thread;
+
+use partial_io::{PartialOp, PartialRead, PartialWrite};
+
+#[test]
+fn trickle_through_cat() {
+    let data: Vec<u8> = (0..4096).map(|i| (i % 251) as u8).collect();
+
+    let mut child = Command::new("cat")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to spawn cat");
+
+    let stdin = child.stdin.take().unwrap();
+    let stdout = child.stdout.take().unwrap();
+
+    // Write from a separate thread so that neither pipe's buffer can fill up
+    // and deadlock the test.
+    let to_write = data.clone();
+    let writer = thread::spawn(move || {
+        let mut partial_writer = PartialWrite::new(stdin, iter::repeat(PartialOp::Limited(1)));
+        partial_writer.write_all(&to_write).unwrap();
+        partial_writer.flush().unwrap();
+        // Dropping the writer on return closes stdin, which makes cat exit.
+        partial_writer.metrics().calls
+    });
+
+    let mut partial_reader = PartialRead::new(stdout, iter::repeat(PartialOp::Limited(1)));
+    let mut out = Vec::new();
+    partial_reader.read_to_end(&mut out).unwrap();
+
+    assert_eq!(writer.join().unwrap(), data.len());
+    assert!(child.wait().unwrap().success());
+    assert_eq!(out, data);
+    assert_eq!(partial_reader.eof_seen_count(), 1);
+}
@@ -0,0 +1,63 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! Tests that `PartialOp::DropAfter` cuts an async writer off for good.
+
+#![cfg(feature = "tokio")]
+
+extern crate futures;
+extern crate partial_io;
+extern crate tokio_io;
+
+use std::io::{self, Write};
+
+use futures::Future;
+use tokio_io::AsyncWrite;
+use tokio_io::io::{flush, write_all};
+
+use partial_io::{PartialAsyncWrite, PartialOp};
+
+#[test]
+fn write_all_drop_after() {
+    let ops = vec![PartialOp::Limited(2), PartialOp::DropAfter(5)];
+    let partial_writer = PartialAsyncWrite::collecting(ops);
+    match write_all(partial_writer, b"Hello, world!").wait() {
+        Ok(_) => panic!("write_all should fail once the connection is dropped"),
+        Err(err) => assert_eq!(err.kind(), io::ErrorKind::ConnectionReset),
+    }
+}
+
+#[test]
+fn drop_after_is_sticky() {
+    let ops = vec![PartialOp::DropAfter(3), PartialOp::Unlimited];
+    let mut partial_writer = PartialAsyncWrite::collecting(ops);
+
+    assert_eq!(partial_writer.write(b"Hello").unwrap(), 3);
+    for _ in 0..3 {
+        let err = partial_writer.write(b"lo").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        let err = partial_writer.flush().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        let err = partial_writer.shutdown().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+    }
+
+    assert_eq!(partial_writer.collected(), b"Hel");
+    assert_eq!(partial_writer.metrics().ops_consumed, 1);
+}
+
+#[test]
+fn flush_drop_after_zero() {
+    let ops = vec![PartialOp::DropAfter(0)];
+    let partial_writer = PartialAsyncWrite::collecting(ops);
+    match flush(partial_writer).wait() {
+        Ok(_) => panic!("flush should fail once the connection is dropped"),
+        Err(err) => assert_eq!(err.kind(), io::ErrorKind::ConnectionReset),
+    }
+}
@@ -0,0 +1,58 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! Tests for `PartialOp::Duplicate`.
+
+extern crate partial_io;
+
+use std::io::{self, Cursor, Read};
+
+use partial_io::{PartialOp, PartialRead};
+
+#[test]
+fn error_is_not_duplicated_over() {
+    let ops = vec![PartialOp::Limited(2),
+                   PartialOp::Duplicate,
+                   PartialOp::Err(io::ErrorKind::Interrupted),
+                   PartialOp::Duplicate];
+    let mut partial_reader = PartialRead::new(Cursor::new(b"abcd".to_vec()), ops);
+    let mut buf = [0; 4];
+
+    assert_eq!(partial_reader.read(&mut buf).unwrap(), 2);
+    assert_eq!(partial_reader.read(&mut buf).unwrap(), 2);
+    assert_eq!(&buf[..2], b"ab");
+    assert_eq!(partial_reader.read(&mut buf).unwrap_err().kind(),
+               io::ErrorKind::Interrupted);
+    // With nothing to duplicate, this reads the rest.
+    assert_eq!(partial_reader.read(&mut buf).unwrap(), 2);
+    assert_eq!(&buf[..2], b"cd");
+}
+
+#[test]
+fn empty_read_is_not_duplicated_over() {
+    let ops = vec![PartialOp::Limited(2), PartialOp::Limited(0), PartialOp::Duplicate];
+    let mut partial_reader = PartialRead::new(Cursor::new(b"abcd".to_vec()), ops);
+    let mut buf = [0; 4];
+
+    assert_eq!(partial_reader.read(&mut buf).unwrap(), 2);
+    assert_eq!(partial_reader.read(&mut buf).unwrap(), 0);
+    assert_eq!(partial_reader.read(&mut buf).unwrap(), 2);
+    assert_eq!(&buf[..2], b"cd");
+}
+
+#[test]
+fn only_read_bytes_are_duplicated() {
+    let ops = vec![PartialOp::ReportExactly(5), PartialOp::Duplicate];
+    let mut partial_reader = PartialRead::new(Cursor::new(b"ab".to_vec()), ops);
+    let mut buf = [0; 8];
+
+    assert_eq!(partial_reader.read(&mut buf).unwrap(), 5);
+    assert_eq!(partial_reader.read(&mut buf).unwrap(), 2);
+    assert_eq!(&buf[..2], b"ab");
+}
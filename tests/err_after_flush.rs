@@ -0,0 +1,66 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! Tests for `PartialOp::ErrAfterFlush`.
+
+extern crate partial_io;
+
+use std::io::{self, IoSlice, Write};
+
+use partial_io::{PartialOp, PartialWrite};
+
+#[test]
+fn failed_flush_does_not_fire() {
+    let ops = vec![PartialOp::ErrAfterFlush(io::ErrorKind::BrokenPipe),
+                   PartialOp::Err(io::ErrorKind::Interrupted)];
+    let mut partial_writer = PartialWrite::new(Vec::new(), ops);
+
+    assert_eq!(partial_writer.write(b"abc").unwrap(), 3);
+    assert_eq!(partial_writer.flush().unwrap_err().kind(), io::ErrorKind::Interrupted);
+    // Still armed, since no flush has succeeded yet.
+    assert_eq!(partial_writer.write(b"def").unwrap(), 3);
+    partial_writer.flush().unwrap();
+
+    let bufs = [IoSlice::new(b"ghi")];
+    assert_eq!(partial_writer.write_vectored(&bufs).unwrap_err().kind(),
+               io::ErrorKind::BrokenPipe);
+    assert_eq!(&partial_writer.get_ref()[..], b"abcdef");
+}
+
+#[test]
+fn taken_by_flush() {
+    let ops = vec![PartialOp::Limited(1), PartialOp::ErrAfterFlush(io::ErrorKind::Other)];
+    let mut partial_writer = PartialWrite::new(Vec::new(), ops);
+
+    assert_eq!(partial_writer.write(b"abc").unwrap(), 1);
+    partial_writer.flush().unwrap();
+    assert_eq!(partial_writer.write(b"bc").unwrap_err().kind(), io::ErrorKind::Other);
+    assert_eq!(partial_writer.last_op_was_injected(), Some(true));
+    assert_eq!(partial_writer.metrics().errors_injected, 1);
+    // The error only fires once.
+    assert_eq!(partial_writer.write(b"bc").unwrap(), 2);
+    assert_eq!(partial_writer.last_op_was_injected(), Some(false));
+    assert_eq!(&partial_writer.get_ref()[..], b"abc");
+}
+
+#[test]
+fn disabled_flush_does_not_fire() {
+    let ops = vec![PartialOp::ErrAfterFlush(io::ErrorKind::BrokenPipe)];
+    let mut partial_writer = PartialWrite::new(Vec::new(), ops);
+
+    assert_eq!(partial_writer.write(b"abc").unwrap(), 3);
+    partial_writer.set_enabled(false);
+    partial_writer.flush().unwrap();
+    partial_writer.set_enabled(true);
+    // Still armed, since the flush passed straight through.
+    assert_eq!(partial_writer.write(b"def").unwrap(), 3);
+    partial_writer.flush().unwrap();
+    assert_eq!(partial_writer.write(b"ghi").unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    assert_eq!(&partial_writer.get_ref()[..], b"abcdef");
+}
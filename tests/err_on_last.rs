@@ -0,0 +1,75 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! Tests for `PartialOp::ErrOnLast`.
+
+extern crate partial_io;
+
+use std::io::{self, Cursor, Read};
+
+use partial_io::{PartialIoError, PartialOp, PartialRead};
+
+const KIND: io::ErrorKind = io::ErrorKind::BrokenPipe;
+
+#[test]
+fn fails_the_read_of_the_last_bytes() {
+    let ops = vec![PartialOp::ErrOnLast(KIND); 3];
+    let mut partial_reader = PartialRead::with_len(Cursor::new(b"abcdef".to_vec()), 6, ops);
+    let mut buf = [0; 4];
+
+    assert_eq!(partial_reader.read(&mut buf).unwrap(), 4);
+    let err = partial_reader.read(&mut buf).unwrap_err();
+    assert_eq!(err.kind(), KIND);
+    assert!(PartialIoError::from_io(&err).is_some());
+    // The failed read didn't reach the inner reader.
+    assert_eq!(partial_reader.get_ref().position(), 4);
+    assert_eq!(partial_reader.read(&mut buf).unwrap_err().kind(), KIND);
+    assert_eq!(partial_reader.read(&mut buf).unwrap(), 2);
+    assert_eq!(&buf[..2], b"ef");
+}
+
+#[test]
+fn fails_the_read_at_eof() {
+    let ops = vec![PartialOp::ErrOnLast(KIND); 2];
+    let mut partial_reader = PartialRead::with_len(Cursor::new(Vec::new()), 0, ops);
+    let mut buf = [0; 2];
+
+    assert_eq!(partial_reader.read(&mut buf).unwrap_err().kind(), KIND);
+    // Even an empty buffer would see the end of the stream.
+    assert_eq!(partial_reader.read(&mut [0; 0]).unwrap_err().kind(), KIND);
+    assert_eq!(partial_reader.read(&mut buf).unwrap(), 0);
+}
+
+#[test]
+fn empty_read_is_not_last() {
+    let ops = vec![PartialOp::ErrOnLast(KIND)];
+    let mut partial_reader = PartialRead::with_len(Cursor::new(b"abc".to_vec()), 3, ops);
+
+    assert_eq!(partial_reader.read(&mut [0; 0]).unwrap(), 0);
+    assert_eq!(partial_reader.read(&mut [0; 4]).unwrap(), 3);
+}
+
+#[test]
+fn read_to_end_sees_the_error() {
+    let ops = vec![PartialOp::Limited(2), PartialOp::Limited(2), PartialOp::ErrOnLast(KIND)];
+    let mut partial_reader = PartialRead::with_len(Cursor::new(b"abcdef".to_vec()), 6, ops);
+    let mut out = Vec::new();
+
+    assert_eq!(partial_reader.read_to_end(&mut out).unwrap_err().kind(), KIND);
+    assert_eq!(&out[..], b"abcd");
+}
+
+#[test]
+fn unknown_length_is_unlimited() {
+    let ops = vec![PartialOp::ErrOnLast(KIND)];
+    let mut partial_reader = PartialRead::new(Cursor::new(b"abc".to_vec()), ops);
+    let mut buf = [0; 4];
+
+    assert_eq!(partial_reader.read(&mut buf).unwrap(), 3);
+}
@@ -0,0 +1,69 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! Tests for `PartialRead::fail_at_offsets`.
+
+extern crate partial_io;
+
+use std::io::{self, Cursor, Read};
+
+use partial_io::{PartialIoError, PartialOp, PartialRead};
+
+fn reader(offsets: Vec<u64>) -> PartialRead<Cursor<Vec<u8>>, Vec<PartialOp>> {
+    let data: Vec<u8> = (0..100).collect();
+    PartialRead::fail_at_offsets(Cursor::new(data), 100, offsets, io::ErrorKind::InvalidData)
+}
+
+#[test]
+fn one_error_per_offset() {
+    let mut partial_reader = reader(vec![70, 10, 12]);
+    let mut buf = [0; 64];
+
+    // The first read spans offsets 10 and 12, so it fails twice.
+    for _ in 0..2 {
+        let err = partial_reader.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(PartialIoError::from_io(&err).unwrap().op_index(), None);
+        assert_eq!(partial_reader.last_op_was_injected(), Some(true));
+    }
+    assert_eq!(partial_reader.read(&mut buf).unwrap(), 64);
+    assert_eq!(partial_reader.last_op_was_injected(), Some(false));
+    assert_eq!(partial_reader.read(&mut buf).unwrap_err().kind(),
+               io::ErrorKind::InvalidData);
+    assert_eq!(partial_reader.read(&mut buf).unwrap(), 36);
+    assert_eq!(partial_reader.read(&mut buf).unwrap(), 0);
+    assert_eq!(partial_reader.metrics().errors_injected, 3);
+}
+
+#[test]
+fn offsets_past_end_never_fail() {
+    let mut partial_reader = reader(vec![100, 1000]);
+    let mut out = Vec::new();
+
+    partial_reader.read_to_end(&mut out).unwrap();
+    assert_eq!(out, (0..100).collect::<Vec<u8>>());
+}
+
+#[test]
+fn read_to_end_retries_past_offsets() {
+    let mut partial_reader = reader(vec![0, 99]);
+    let mut out = Vec::new();
+
+    // read_to_end doesn't retry InvalidData, so drive it by hand.
+    let mut errors = 0;
+    loop {
+        match partial_reader.read_to_end(&mut out) {
+            Ok(_) => break,
+            Err(ref err) if err.kind() == io::ErrorKind::InvalidData => errors += 1,
+            Err(err) => panic!("unexpected error: {}", err),
+        }
+    }
+    assert_eq!(errors, 2);
+    assert_eq!(out, (0..100).collect::<Vec<u8>>());
+}
@@ -0,0 +1,53 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! Tests for `PartialAsyncWrite::finish`.
+
+#![cfg(feature = "tokio")]
+
+extern crate futures;
+extern crate partial_io;
+extern crate tokio_io;
+
+use std::io;
+
+use futures::Future;
+use tokio_io::io::write_all;
+
+use partial_io::{PartialAsyncWrite, PartialIoError, PartialOp};
+
+#[test]
+fn ops_left_over() {
+    let ops = vec![PartialOp::Unlimited, PartialOp::Unlimited, PartialOp::Unlimited];
+    let partial_writer = PartialAsyncWrite::collecting(ops);
+    let (partial_writer, _) = write_all(partial_writer, b"Hello").wait().unwrap();
+
+    // The write and the flush only use up two of the three operations.
+    let err = partial_writer.finish().wait().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Other);
+    // This reports a problem with the test rather than injecting a fault.
+    assert!(!PartialIoError::from_io(&err).unwrap().is_injected());
+}
+
+#[test]
+fn flush_error() {
+    let ops = vec![PartialOp::Err(io::ErrorKind::WouldBlock),
+                   PartialOp::Err(io::ErrorKind::BrokenPipe)];
+    let partial_writer = PartialAsyncWrite::collecting(ops);
+    let err = partial_writer.finish().wait().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+}
+
+#[test]
+fn dropped_connection() {
+    let ops = vec![PartialOp::DropAfter(0)];
+    let partial_writer = PartialAsyncWrite::collecting(ops);
+    let err = partial_writer.finish().wait().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+}
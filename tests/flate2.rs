@@ -0,0 +1,46 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! Tests that compression encoders and decoders work over partial readers and
+//! writers.
+
+extern crate flate2;
+extern crate partial_io;
+
+use std::io::{Cursor, Read, Write};
+use std::iter;
+
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+
+use partial_io::{PartialOp, PartialRead, PartialWrite};
+
+fn test_data() -> Vec<u8> {
+    (0..10000u32).flat_map(|i| format!("line {}\n", i % 97).into_bytes()).collect()
+}
+
+#[test]
+fn gzip_roundtrip_one_byte_at_a_time() {
+    let data = test_data();
+
+    let partial_writer = PartialWrite::new(Vec::new(), iter::repeat(PartialOp::Limited(1)));
+    let mut encoder = GzEncoder::new(partial_writer, Compression::default());
+    encoder.write_all(&data).unwrap();
+    let partial_writer = encoder.finish().unwrap();
+    let compressed = partial_writer.into_inner();
+
+    let partial_reader = PartialRead::new(Cursor::new(compressed),
+                                          iter::repeat(PartialOp::Limited(1)));
+    let mut decoder = GzDecoder::new(partial_reader);
+    let mut out = Vec::new();
+    decoder.read_to_end(&mut out).unwrap();
+
+    assert_eq!(out, data);
+}
@@ -0,0 +1,52 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! Tests for reading operations from `PARTIAL_IO_DEFAULT_OPS`.
+
+#[macro_use]
+extern crate lazy_static;
+extern crate partial_io;
+
+use std::env;
+use std::io::Write;
+use std::panic;
+use std::sync::Mutex;
+
+use partial_io::{DEFAULT_OPS_ENV, PartialOp, PartialWrite};
+
+lazy_static! {
+    // The environment is shared by every test in this binary, so tests that
+    // set the variable take turns.
+    static ref ENV_LOCK: Mutex<()> = Mutex::new(());
+}
+
+#[test]
+fn env_overrides_default() {
+    let _guard = ENV_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    env::set_var(DEFAULT_OPS_ENV, "L2 L1");
+    let mut partial_writer = PartialWrite::from_env_or(Vec::new(), vec![PartialOp::Limited(3)]);
+    env::remove_var(DEFAULT_OPS_ENV);
+
+    assert_eq!(partial_writer.write(b"Hello").unwrap(), 2);
+    assert_eq!(partial_writer.write(b"llo").unwrap(), 1);
+    assert_eq!(partial_writer.write(b"lo").unwrap(), 2);
+    assert_eq!(&partial_writer.get_ref()[..], b"Hello");
+}
+
+#[test]
+fn invalid_env_panics() {
+    let _guard = ENV_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    env::set_var(DEFAULT_OPS_ENV, "L1 X");
+    let res = panic::catch_unwind(|| PartialWrite::from_env_or(Vec::new(), vec![]));
+    env::remove_var(DEFAULT_OPS_ENV);
+
+    let err = res.err().expect("an invalid pattern should panic");
+    let msg = err.downcast_ref::<String>().expect("panic message should be a String");
+    assert!(msg.starts_with("invalid PARTIAL_IO_DEFAULT_OPS: "), "{}", msg);
+}
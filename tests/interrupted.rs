@@ -0,0 +1,65 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! Tests that a bounded retry loop terminates against a reader that is
+//! interrupted forever.
+
+extern crate partial_io;
+
+use std::io::{self, Cursor, Read};
+
+use partial_io::{PartialRead, always_interrupted};
+
+const MAX_RETRIES: usize = 16;
+
+/// Reads until the end of the stream, giving up after `MAX_RETRIES`
+/// consecutive interruptions. Unlike `Read::read_to_end`, this never hangs.
+fn read_to_end_bounded<R: Read>(reader: &mut R, out: &mut Vec<u8>) -> io::Result<usize> {
+    let mut buf = [0; 64];
+    let mut retries = 0;
+    let start = out.len();
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => return Ok(out.len() - start),
+            Ok(n) => {
+                out.extend_from_slice(&buf[..n]);
+                retries = 0;
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {
+                retries += 1;
+                if retries == MAX_RETRIES {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "too many interruptions"));
+                }
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[test]
+fn bounded_loop_terminates() {
+    let mut partial_reader = PartialRead::new(Cursor::new(b"Hello".to_vec()), always_interrupted());
+    let mut out = Vec::new();
+    let err = read_to_end_bounded(&mut partial_reader, &mut out).unwrap_err();
+
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert!(out.is_empty());
+    assert_eq!(partial_reader.metrics().calls, MAX_RETRIES);
+    assert_eq!(partial_reader.metrics().inner_io_calls, 0);
+}
+
+#[test]
+fn bounded_loop_recovers() {
+    let ops = always_interrupted().take(MAX_RETRIES - 1);
+    let mut partial_reader = PartialRead::new(Cursor::new(b"Hello".to_vec()), ops);
+    let mut out = Vec::new();
+
+    assert_eq!(read_to_end_bounded(&mut partial_reader, &mut out).unwrap(), 5);
+    assert_eq!(&out, b"Hello");
+}
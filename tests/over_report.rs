@@ -0,0 +1,69 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! Tests that the wrappers pass through counts from an underlying writer that
+//! reports more bytes than it was given.
+
+#[cfg(feature = "tokio")]
+extern crate futures;
+extern crate partial_io;
+#[cfg(feature = "tokio")]
+extern crate tokio_io;
+
+use std::io::Write;
+
+use partial_io::{PartialOp, PartialWrite};
+
+fn over_reporting() -> PartialWrite<Vec<u8>, Vec<PartialOp>> {
+    PartialWrite::new(Vec::new(), vec![PartialOp::ReportExactly(100)])
+}
+
+#[test]
+fn partial_write() {
+    let mut partial_writer = PartialWrite::new(over_reporting(), vec![]);
+    assert_eq!(partial_writer.write(b"abc").unwrap(), 100);
+    assert_eq!(&partial_writer.get_ref().get_ref()[..], b"abc");
+}
+
+#[test]
+fn recorder() {
+    let mut partial_writer = PartialWrite::recorder(over_reporting());
+    assert_eq!(partial_writer.write(b"abc").unwrap(), 100);
+    assert_eq!(format!("{:?}", partial_writer.recorded_ops().unwrap()),
+               "[Unlimited]");
+}
+
+#[test]
+fn tee() {
+    let mut partial_writer = PartialWrite::new(over_reporting(), vec![]);
+    partial_writer.tee_writes(Vec::new());
+    assert_eq!(partial_writer.write(b"abc").unwrap(), 100);
+    assert_eq!(&partial_writer.get_ref().get_ref()[..], b"abc");
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn coalescing() {
+    use std::io::Cursor;
+
+    use futures::Future;
+    use tokio_io::io::{flush, write_all};
+
+    use partial_io::{CoalescingPartialAsyncWrite, PartialAsyncWrite};
+
+    let ops = vec![PartialOp::ReportExactly(100)];
+    let inner = PartialAsyncWrite::new(Cursor::new(Vec::new()), ops);
+    let mut partial_writer = CoalescingPartialAsyncWrite::new(inner, vec![]);
+    partial_writer.verify_monotonic();
+
+    let (partial_writer, _) = write_all(partial_writer, b"abc").wait().unwrap();
+    let partial_writer = flush(partial_writer).wait().unwrap();
+    assert!(partial_writer.buffer().is_empty());
+    assert_eq!(&partial_writer.get_ref().get_ref().get_ref()[..], b"abc");
+}
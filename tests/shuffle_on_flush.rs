@@ -0,0 +1,50 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! Tests for `CoalescingPartialAsyncWrite::shuffle_on_flush`.
+
+#![cfg(feature = "tokio")]
+
+extern crate partial_io;
+
+use std::io::{self, Cursor, Write};
+
+use partial_io::{CoalescingPartialAsyncWrite, PartialOp};
+
+fn sorted(data: &[u8]) -> Vec<u8> {
+    let mut data = data.to_vec();
+    data.sort();
+    data
+}
+
+#[test]
+fn retry_keeps_the_shuffled_order() {
+    let ops = vec![PartialOp::Limited(4), PartialOp::Err(io::ErrorKind::Interrupted)];
+    let mut partial_writer = CoalescingPartialAsyncWrite::new(Cursor::new(Vec::new()), ops);
+    partial_writer.shuffle_on_flush(42);
+
+    partial_writer.write_all(b"Hello, world!").unwrap();
+    assert_eq!(partial_writer.flush().unwrap_err().kind(), io::ErrorKind::Interrupted);
+    let first = partial_writer.get_ref().get_ref().clone();
+    let rest = partial_writer.buffer().to_vec();
+    assert_eq!(first.len(), 4);
+    assert_eq!(rest.len(), 9);
+
+    partial_writer.write_all(b"abcdefghij").unwrap();
+    partial_writer.flush().unwrap();
+    let out = partial_writer.into_inner().into_inner();
+
+    // The retry picks up the shuffled bytes where the failed flush left off.
+    assert_eq!(&out[..4], &first[..]);
+    assert_eq!(&out[4..13], &rest[..]);
+    assert_eq!(sorted(&out[..13]), sorted(b"Hello, world!"));
+    // The later bytes are only shuffled among themselves.
+    assert_ne!(&out[13..], b"abcdefghij");
+    assert_eq!(sorted(&out[13..]), sorted(b"abcdefghij"));
+}
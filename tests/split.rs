@@ -0,0 +1,93 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! Tests for wrapping the halves of a split duplex stream independently.
+
+#![cfg(feature = "tokio")]
+
+extern crate futures;
+extern crate partial_io;
+extern crate tokio_io;
+
+use std::io::{self, Cursor, Read, Write};
+
+use futures::{Future, Poll};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_io::io::{read_exact, write_all};
+
+use partial_io::{PartialAsyncRead, PartialAsyncWrite, PartialOp};
+
+/// An in-memory duplex stream: reads come from `input`, and writes go to
+/// `output`.
+struct Duplex {
+    input: Cursor<Vec<u8>>,
+    output: Vec<u8>,
+}
+
+impl Read for Duplex {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for Duplex {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncRead for Duplex {}
+
+impl AsyncWrite for Duplex {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        Ok(().into())
+    }
+}
+
+fn assert_send<T: Send>(_: &T) {}
+
+#[test]
+fn independent_halves() {
+    let duplex = Duplex {
+        input: Cursor::new(b"Hello, reader!".to_vec()),
+        output: Vec::new(),
+    };
+    let (read_half, write_half) = duplex.split();
+
+    let read_ops = vec![PartialOp::Err(io::ErrorKind::WouldBlock),
+                        PartialOp::Limited(2),
+                        PartialOp::Err(io::ErrorKind::WouldBlock),
+                        PartialOp::Limited(5)];
+    let write_ops = vec![PartialOp::Limited(1),
+                         PartialOp::Err(io::ErrorKind::WouldBlock),
+                         PartialOp::Limited(3)];
+    let partial_reader = PartialAsyncRead::new(read_half, read_ops);
+    let partial_writer = PartialAsyncWrite::new(write_half, write_ops);
+    assert_send(&partial_reader);
+    assert_send(&partial_writer);
+
+    // Polling both futures on the same task interleaves the reads and writes.
+    let read_fut = read_exact(partial_reader, vec![0; 14]);
+    let write_fut = write_all(partial_writer, b"Hello, writer!");
+    let ((partial_reader, out), (partial_writer, _)) = read_fut.join(write_fut).wait().unwrap();
+    assert_eq!(&out, b"Hello, reader!");
+
+    // Each half only consumed its own operations.
+    assert_eq!(partial_reader.metrics().ops_consumed, 4);
+    assert_eq!(partial_reader.metrics().errors_injected, 2);
+    assert_eq!(partial_writer.metrics().ops_consumed, 3);
+    assert_eq!(partial_writer.metrics().errors_injected, 1);
+
+    let duplex = partial_reader.into_inner().unsplit(partial_writer.into_inner());
+    assert_eq!(&duplex.output, b"Hello, writer!");
+}
@@ -0,0 +1,55 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! Tests for `PartialWrite::tee_writes`.
+
+extern crate partial_io;
+
+use std::io::{self, IoSlice, Write};
+
+use partial_io::{PartialOp, PartialWrite};
+
+#[test]
+fn mirrors_accepted_bytes() {
+    let ops = vec![PartialOp::Limited(3),
+                   PartialOp::Err(io::ErrorKind::Interrupted),
+                   PartialOp::Limited(2),
+                   PartialOp::Err(io::ErrorKind::WouldBlock)];
+    let mut partial_writer = PartialWrite::new(Vec::new(), ops);
+    partial_writer.tee_writes(Vec::new());
+
+    assert_eq!(partial_writer.write(b"Hello").unwrap(), 3);
+    assert_eq!(partial_writer.tee_sink::<Vec<u8>>().unwrap(), b"Hel");
+    assert_eq!(partial_writer.write(b"lo").unwrap_err().kind(),
+               io::ErrorKind::Interrupted);
+    let bufs = [IoSlice::new(b"lo"), IoSlice::new(b", ")];
+    assert_eq!(partial_writer.write_vectored(&bufs).unwrap(), 2);
+    assert_eq!(partial_writer.write(b", world!").unwrap_err().kind(),
+               io::ErrorKind::WouldBlock);
+    assert_eq!(partial_writer.write(b", world!").unwrap(), 8);
+
+    let log: Vec<u8> = partial_writer.take_tee_sink().unwrap();
+    assert_eq!(&log[..], b"Hello, world!");
+    assert_eq!(&partial_writer.get_ref()[..], b"Hello, world!");
+    // Once the sink has been taken, nothing is copied.
+    assert_eq!(partial_writer.write(b"!").unwrap(), 1);
+    assert!(partial_writer.tee_sink::<Vec<u8>>().is_none());
+}
+
+#[test]
+fn wrong_type_keeps_the_sink() {
+    let mut partial_writer = PartialWrite::new(Vec::new(), vec![]);
+    assert!(partial_writer.take_tee_sink::<Vec<u8>>().is_none());
+
+    partial_writer.tee_writes(Vec::new());
+    assert!(partial_writer.take_tee_sink::<io::Sink>().is_none());
+    assert_eq!(partial_writer.write(b"abc").unwrap(), 3);
+    let log: Vec<u8> = partial_writer.take_tee_sink().unwrap();
+    assert_eq!(&log[..], b"abc");
+}
@@ -0,0 +1,45 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! Tests for `PartialOp::VectoredLimited`.
+
+extern crate partial_io;
+
+use std::io::{IoSlice, Write};
+
+use partial_io::{PartialOp, PartialWrite};
+
+#[test]
+fn stops_after_short_slice() {
+    let ops = vec![PartialOp::VectoredLimited(vec![3, 1, 3]), PartialOp::VectoredLimited(vec![])];
+    let mut partial_writer = PartialWrite::new(Vec::new(), ops);
+    partial_writer.record_write_sizes();
+    let bufs = [IoSlice::new(b"abc"), IoSlice::new(b"def"), IoSlice::new(b"ghi")];
+
+    // The second slice is cut short, so the third one is left out even though
+    // its cap would allow it.
+    assert_eq!(partial_writer.write_vectored(&bufs).unwrap(), 4);
+    // No caps means no slices.
+    assert_eq!(partial_writer.write_vectored(&bufs).unwrap(), 0);
+    // Without an operation, only the first non-empty slice is written.
+    let bufs = [IoSlice::new(b""), IoSlice::new(b"ef"), IoSlice::new(b"ghi")];
+    assert_eq!(partial_writer.write_vectored(&bufs).unwrap(), 2);
+
+    assert_eq!(partial_writer.get_ref(), b"abcdef");
+    assert_eq!(partial_writer.inner_write_sizes(), &[9, 9, 5]);
+}
+
+#[test]
+fn plain_write_uses_first_cap() {
+    let ops = vec![PartialOp::VectoredLimited(vec![2, 5])];
+    let mut partial_writer = PartialWrite::new(Vec::new(), ops);
+
+    assert_eq!(partial_writer.write(b"Hello").unwrap(), 2);
+    assert_eq!(partial_writer.get_ref(), b"He");
+}
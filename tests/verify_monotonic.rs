@@ -0,0 +1,111 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! Tests for `verify_monotonic` against the wrappers' own limiting logic.
+
+extern crate partial_io;
+
+use std::io::{self, IoSlice, Write};
+
+use partial_io::{PartialOp, PartialWrite};
+
+const DATA: &[u8] = b"The quick brown fox jumps over the lazy dog.";
+
+#[test]
+fn partial_write_ops() {
+    let ops = vec![PartialOp::Limited(3),
+                   PartialOp::Err(io::ErrorKind::Interrupted),
+                   PartialOp::AlignedTo(8),
+                   PartialOp::WriteLimited(5),
+                   PartialOp::VectoredLimited(vec![2]),
+                   PartialOp::AsyncWriteZero,
+                   PartialOp::Limited(1)];
+    let mut partial_writer = PartialWrite::new(Vec::new(), ops).with_watermark(32);
+    partial_writer.verify_monotonic();
+
+    let mut written = 0;
+    while written < DATA.len() {
+        match partial_writer.write(&DATA[written..]) {
+            Ok(n) => written += n,
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => partial_writer.drain(32),
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => panic!("unexpected error: {}", err),
+        }
+    }
+    assert_eq!(&partial_writer.get_ref()[..], DATA);
+}
+
+#[test]
+fn partial_write_vectored() {
+    let ops = vec![PartialOp::VectoredLimited(vec![3, 10, 1]),
+                   PartialOp::VectoredLimited(vec![0]),
+                   PartialOp::Limited(2)];
+    let mut partial_writer = PartialWrite::new(Vec::new(), ops);
+    partial_writer.verify_monotonic();
+
+    let bufs = [IoSlice::new(b"abc"), IoSlice::new(b"defg")];
+    assert_eq!(partial_writer.write_vectored(&bufs).unwrap(), 7);
+    assert_eq!(partial_writer.write_vectored(&bufs).unwrap(), 0);
+    assert_eq!(partial_writer.write_vectored(&bufs[1..]).unwrap(), 2);
+    assert_eq!(&partial_writer.get_ref()[..], b"abcdefgde");
+}
+
+#[test]
+fn partial_write_report_exactly() {
+    // Reporting fewer bytes than were written makes the caller repeat them,
+    // which is an intentional fault and not a violation.
+    let ops = vec![PartialOp::ReportExactly(1), PartialOp::Limited(2)];
+    let mut partial_writer = PartialWrite::new(Vec::new(), ops);
+    partial_writer.verify_monotonic();
+
+    assert_eq!(partial_writer.write(b"abc").unwrap(), 1);
+    assert_eq!(partial_writer.write(b"bc").unwrap(), 2);
+    assert_eq!(&partial_writer.get_ref()[..], b"abcbc");
+}
+
+#[cfg(feature = "tokio")]
+mod coalescing {
+    use std::io::{self, Cursor, Write};
+
+    use partial_io::{CoalescingPartialAsyncWrite, PartialOp};
+
+    use super::DATA;
+
+    #[test]
+    fn retried_flush() {
+        let ops = vec![PartialOp::Limited(5),
+                       PartialOp::Err(io::ErrorKind::Interrupted),
+                       PartialOp::AlignedTo(4),
+                       PartialOp::AsyncWriteZero];
+        let mut partial_writer = CoalescingPartialAsyncWrite::new(Cursor::new(Vec::new()), ops);
+        partial_writer.write_all(&DATA[..10]).unwrap();
+        partial_writer.verify_monotonic();
+        partial_writer.write_all(&DATA[10..20]).unwrap();
+
+        assert_eq!(partial_writer.flush().unwrap_err().kind(), io::ErrorKind::Interrupted);
+        partial_writer.write_all(&DATA[20..]).unwrap();
+        assert_eq!(partial_writer.flush().unwrap_err().kind(), io::ErrorKind::WriteZero);
+        partial_writer.flush().unwrap();
+        assert_eq!(&partial_writer.get_ref().get_ref()[..], DATA);
+    }
+
+    #[test]
+    fn shuffled() {
+        let ops = vec![PartialOp::Limited(7), PartialOp::Err(io::ErrorKind::Interrupted)];
+        let mut partial_writer = CoalescingPartialAsyncWrite::new(Cursor::new(Vec::new()), ops);
+        partial_writer.verify_monotonic();
+        partial_writer.shuffle_on_flush(7);
+
+        partial_writer.write_all(&DATA[..20]).unwrap();
+        assert_eq!(partial_writer.flush().unwrap_err().kind(), io::ErrorKind::Interrupted);
+        partial_writer.write_all(&DATA[20..]).unwrap();
+        partial_writer.flush().unwrap();
+        assert_eq!(partial_writer.get_ref().get_ref().len(), DATA.len());
+    }
+}
@@ -0,0 +1,42 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! Tests for `PartialWrite::with_watermark`.
+
+extern crate partial_io;
+
+use std::io::{self, Write};
+
+use partial_io::PartialWrite;
+
+#[test]
+fn watermark_error_is_injected() {
+    let mut partial_writer = PartialWrite::new(Vec::new(), vec![]).with_watermark(4);
+    assert_eq!(partial_writer.write(b"Hello").unwrap(), 4);
+    assert_eq!(partial_writer.last_op_was_injected(), Some(false));
+
+    let err = partial_writer.write(b"o").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    assert_eq!(partial_writer.last_op_was_injected(), Some(true));
+    assert_eq!(partial_writer.metrics().errors_injected, 1);
+    assert_eq!(partial_writer.metrics().ops_consumed, 0);
+}
+
+#[test]
+fn disabled_writes_pass_through() {
+    let mut partial_writer = PartialWrite::new(Vec::new(), vec![]).with_watermark(4);
+    partial_writer.set_enabled(false);
+    assert_eq!(partial_writer.write(b"Hello, world!").unwrap(), 13);
+    assert_eq!(partial_writer.write(b"!").unwrap(), 1);
+    assert_eq!(partial_writer.pending(), 0);
+
+    partial_writer.set_enabled(true);
+    assert_eq!(partial_writer.write(b"Hello").unwrap(), 4);
+    assert_eq!(partial_writer.write(b"o").unwrap_err().kind(), io::ErrorKind::WouldBlock);
+}
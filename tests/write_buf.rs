@@ -0,0 +1,73 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! Tests that `AsyncWrite::write_buf` advances a `bytes::Buf` correctly under
+//! short writes.
+
+#![cfg(feature = "tokio")]
+
+extern crate bytes;
+#[macro_use]
+extern crate futures;
+extern crate partial_io;
+extern crate tokio_io;
+
+use std::io::{self, Cursor};
+use std::iter;
+
+use bytes::Buf;
+use futures::{Async, Future, Poll};
+use futures::future::poll_fn;
+use tokio_io::AsyncWrite;
+
+use partial_io::{PartialAsyncWrite, PartialOp, PartialOpIterExt};
+
+/// Writes out all of `buf` with `write_buf`, like `write_all` does for slices.
+fn poll_write_all_buf<W, B>(writer: &mut W, buf: &mut B) -> Poll<(), io::Error>
+    where W: AsyncWrite,
+          B: Buf
+{
+    while buf.has_remaining() {
+        if try_ready!(writer.write_buf(buf)) == 0 {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "write_buf wrote nothing"));
+        }
+    }
+    Ok(Async::Ready(()))
+}
+
+fn chained_buf() -> bytes::buf::Chain<Cursor<Vec<u8>>, Cursor<Vec<u8>>> {
+    Cursor::new(b"Hello, ".to_vec()).chain(Cursor::new(b"world!".to_vec()))
+}
+
+#[test]
+fn write_buf_trickle() {
+    let ops = iter::repeat(PartialOp::Limited(1));
+    let mut partial_writer = PartialAsyncWrite::collecting(ops);
+    let mut buf = chained_buf();
+
+    poll_fn(|| poll_write_all_buf(&mut partial_writer, &mut buf)).wait().unwrap();
+
+    assert!(!buf.has_remaining());
+    assert_eq!(partial_writer.collected(), b"Hello, world!");
+    assert_eq!(partial_writer.inner_io_calls(), 13);
+}
+
+#[test]
+fn write_buf_would_block() {
+    // Short writes that straddle the boundary between the two chained buffers,
+    // interleaved with WouldBlock.
+    let ops = iter::repeat(PartialOp::Limited(3)).with_errors_every(1, io::ErrorKind::WouldBlock);
+    let mut partial_writer = PartialAsyncWrite::collecting(ops);
+    let mut buf = chained_buf();
+
+    poll_fn(|| poll_write_all_buf(&mut partial_writer, &mut buf)).wait().unwrap();
+
+    assert!(!buf.has_remaining());
+    assert_eq!(partial_writer.collected(), b"Hello, world!");
+}
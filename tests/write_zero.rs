@@ -0,0 +1,68 @@
+/*
+ *  Copyright (c) 2017-present, Facebook, Inc.
+ *  All rights reserved.
+ *
+ *  This source code is licensed under the BSD-style license found in the
+ *  LICENSE file in the root directory of this source tree. An additional grant
+ *  of patent rights can be found in the PATENTS file in the same directory.
+ *
+ */
+//! Tests that `PartialOp::AsyncWriteZero` is turned into an error by async
+//! consumers, rather than retried forever.
+
+#![cfg(feature = "tokio")]
+
+extern crate futures;
+extern crate partial_io;
+extern crate tokio_io;
+
+use std::io::{self, Cursor};
+
+use futures::Future;
+use tokio_io::io::{flush, write_all};
+
+use partial_io::{CoalescingPartialAsyncWrite, OpKind, PartialAsyncWrite, PartialIoError, PartialOp};
+
+#[test]
+fn write_all_write_zero() {
+    let ops = vec![PartialOp::Err(io::ErrorKind::WouldBlock),
+                   PartialOp::Limited(2),
+                   PartialOp::AsyncWriteZero];
+    let partial_writer = PartialAsyncWrite::collecting(ops);
+    let err = match write_all(partial_writer, b"Hello, world!").wait() {
+        Ok(_) => panic!("write_all should fail once the writer stops accepting data"),
+        Err(err) => err,
+    };
+    assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+}
+
+#[test]
+fn coalescing_flush_write_zero() {
+    let ops = vec![PartialOp::Limited(2), PartialOp::AsyncWriteZero];
+    let mut partial_writer = CoalescingPartialAsyncWrite::new(Cursor::new(Vec::new()), ops);
+    io::Write::write_all(&mut partial_writer, b"Hello").unwrap();
+    let err = match flush(partial_writer).wait() {
+        Ok(_) => panic!("flush should fail once the writer stops accepting data"),
+        Err(err) => err,
+    };
+    assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+    let injected = PartialIoError::from_io(&err).expect("error should be injected");
+    assert_eq!(injected.op_kind(), OpKind::Flush);
+    assert_eq!(injected.op_index(), Some(1));
+    assert!(injected.is_injected());
+}
+
+#[test]
+fn coalescing_flush_inner_write_zero() {
+    // The underlying writer accepts no data, without partial-io injecting it.
+    let inner = PartialAsyncWrite::new(Cursor::new(Vec::new()), vec![PartialOp::Limited(0)]);
+    let mut partial_writer = CoalescingPartialAsyncWrite::new(inner, vec![]);
+    io::Write::write_all(&mut partial_writer, b"Hello").unwrap();
+    let err = io::Write::flush(&mut partial_writer).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+    let reported = PartialIoError::from_io(&err).expect("error should be from partial-io");
+    assert_eq!(reported.op_kind(), OpKind::Flush);
+    assert_eq!(reported.op_index(), None);
+    assert!(!reported.is_injected());
+    assert_eq!(partial_writer.metrics().errors_injected, 0);
+}